@@ -3,9 +3,9 @@ pub fn main() {}
 #[cfg(test)]
 mod tests {
 
-    use ggml_rs::io::{static_tensor, ModelIO};
-    use ggml_rs::Context;
-    use ggml_rs::Dimension;
+    use ggml_rs::io::{static_tensor, tensor_name, tensor_path, ModelIO, StaticLayer, TensorNames};
+    use ggml_rs::{Context, ContextBuilder};
+    use ggml_rs::{DataType, Dimension, Tensor};
     use std::fs::File;
     use std::io::Seek;
 
@@ -21,7 +21,7 @@ mod tests {
     #[static_tensor(ggml_datatype = i8, ggml_dim = D3)]
     struct CubeU8Param([[[i8; 2]; 2]; 2]);
 
-    // #[static_layer]
+    #[derive(StaticLayer)]
     struct BasicStaticLayer {
         a: EightBitParam,
         b: FourByteParam,
@@ -43,122 +43,220 @@ mod tests {
     #[test]
     fn test_reader_1d() {
         let mut buffer: ManagedMemory = ManagedMemory([0; MEMORY_SIZE]);
-        let ctx = Context::init_managed(&mut buffer.0);
+        ContextBuilder::new(0)
+            .buffer(&mut buffer.0)
+            .build_scoped(|ctx| {
+                let test_file_path = test_file!("resources/model.bin");
+                let mut reader = File::open(&test_file_path).expect("Failed to open file");
 
-        let test_file_path = test_file!("resources/model.bin");
-        let mut reader = File::open(&test_file_path).expect("Failed to open file");
+                for i in 0..16 {
+                    assert_eq!(reader.stream_position().unwrap(), i);
 
-        for i in 0..16 {
-            assert_eq!(reader.stream_position().unwrap(), i);
-
-            let read_result =
-                EightBitParam::read_to_tensor(&ctx, &mut reader, Dimension::D1, vec![None]);
-            assert!(read_result.is_ok());
-            let tensor = read_result.unwrap();
-            assert_eq!(tensor.nbytes(), 1);
-        }
-        assert!(
-            EightBitParam::read_to_tensor(&ctx, &mut reader, Dimension::D1, vec!(None)).is_err()
-        );
+                    let read_result =
+                        EightBitParam::read_to_tensor(ctx, &mut reader, Dimension::D1, vec![None]);
+                    assert!(read_result.is_ok());
+                    let tensor = read_result.unwrap();
+                    assert_eq!(tensor.nbytes(), 1);
+                }
+                assert!(
+                    EightBitParam::read_to_tensor(ctx, &mut reader, Dimension::D1, vec!(None))
+                        .is_err()
+                );
+            })
+            .unwrap();
     }
 
     #[test]
     fn test_reader_1d_2() {
         let mut buffer: ManagedMemory = ManagedMemory([0; MEMORY_SIZE]);
-        let ctx = Context::init_managed(&mut buffer.0);
-
-        let test_file_path = test_file!("resources/model.bin");
-        let mut reader = File::open(&test_file_path).expect("Failed to open file");
+        ContextBuilder::new(0)
+            .buffer(&mut buffer.0)
+            .build_scoped(|ctx| {
+                let test_file_path = test_file!("resources/model.bin");
+                let mut reader = File::open(&test_file_path).expect("Failed to open file");
 
-        for i in 0..4 {
-            assert_eq!(reader.stream_position().unwrap(), i * 4);
+                for i in 0..4 {
+                    assert_eq!(reader.stream_position().unwrap(), i * 4);
 
-            let read_result =
-                FourByteParam::read_to_tensor(&ctx, &mut reader, Dimension::D1, vec![Some(1)]);
+                    let read_result = FourByteParam::read_to_tensor(
+                        ctx,
+                        &mut reader,
+                        Dimension::D1,
+                        vec![Some(1)],
+                    );
 
-            assert_eq!(read_result.unwrap().nbytes(), 4);
-        }
-        assert!(
-            FourByteParam::read_to_tensor(&ctx, &mut reader, Dimension::D1, vec!(None)).is_err()
-        );
+                    assert_eq!(read_result.unwrap().nbytes(), 4);
+                }
+                assert!(
+                    FourByteParam::read_to_tensor(ctx, &mut reader, Dimension::D1, vec!(None))
+                        .is_err()
+                );
+            })
+            .unwrap();
     }
 
     #[test]
     fn test_reader_2d() {
         let mut buffer: ManagedMemory = ManagedMemory([0; MEMORY_SIZE]);
-        let ctx = Context::init_managed(&mut buffer.0);
+        ContextBuilder::new(0)
+            .buffer(&mut buffer.0)
+            .build_scoped(|ctx| {
+                let test_file_path = test_file!("resources/model64bytes.bin");
+                let mut reader = File::open(&test_file_path).expect("Failed to open file");
 
-        let test_file_path = test_file!("resources/model64bytes.bin");
-        let mut reader = File::open(&test_file_path).expect("Failed to open file");
-
-        for _ in 0..2 {
-            let read_result = RectU8Param::read_to_tensor(
-                &ctx,
-                &mut reader,
-                Dimension::D2,
-                vec![Some(16), Some(2)],
-            );
-            assert!(read_result.is_ok());
-            let tensor = read_result.unwrap();
-            assert_eq!(tensor.nbytes(), 32);
-        }
-        assert!(
-            RectU8Param::read_to_tensor(&ctx, &mut reader, Dimension::D2, vec!(None, None))
-                .is_err()
-        );
+                for _ in 0..2 {
+                    let read_result = RectU8Param::read_to_tensor(
+                        ctx,
+                        &mut reader,
+                        Dimension::D2,
+                        vec![Some(16), Some(2)],
+                    );
+                    assert!(read_result.is_ok());
+                    let tensor = read_result.unwrap();
+                    assert_eq!(tensor.nbytes(), 32);
+                }
+                assert!(RectU8Param::read_to_tensor(
+                    ctx,
+                    &mut reader,
+                    Dimension::D2,
+                    vec!(None, None)
+                )
+                .is_err());
+            })
+            .unwrap();
     }
 
     #[test]
     fn test_reader_3d() {
         let mut buffer: ManagedMemory = ManagedMemory([0; MEMORY_SIZE]);
-        let ctx = Context::init_managed(&mut buffer.0);
+        ContextBuilder::new(0)
+            .buffer(&mut buffer.0)
+            .build_scoped(|ctx| {
+                let test_file_path = test_file!("resources/model64bytes.bin");
+                let mut reader = File::open(&test_file_path).expect("Failed to open file");
+
+                for _ in 0..8 {
+                    let read_result = CubeU8Param::read_to_tensor(
+                        ctx,
+                        &mut reader,
+                        Dimension::D3,
+                        vec![Some(2), Some(2), Some(2)],
+                    );
+                    assert!(read_result.is_ok());
+                    let tensor = read_result.unwrap();
+                    assert_eq!(tensor.nbytes(), 8);
+                }
+                assert!(CubeU8Param::read_to_tensor(
+                    ctx,
+                    &mut reader,
+                    Dimension::D3,
+                    vec!(None, None, None)
+                )
+                .is_err());
+            })
+            .unwrap();
+    }
+
+    #[derive(StaticLayer)]
+    struct SmallLayer {
+        a: EightBitParam,
+        b: FourByteParam,
+    }
 
+    #[derive(StaticLayer)]
+    struct Stack {
+        layers: [SmallLayer; 3],
+    }
+
+    #[test]
+    fn test_read_layer() {
+        let ctx = Context::init(MEMORY_SIZE);
         let test_file_path = test_file!("resources/model64bytes.bin");
         let mut reader = File::open(&test_file_path).expect("Failed to open file");
 
-        for _ in 0..8 {
-            let read_result = CubeU8Param::read_to_tensor(
-                &ctx,
-                &mut reader,
-                Dimension::D3,
-                vec![Some(2), Some(2), Some(2)],
-            );
-            assert!(read_result.is_ok());
-            let tensor = read_result.unwrap();
-            assert_eq!(tensor.nbytes(), 8);
-        }
-        assert!(CubeU8Param::read_to_tensor(
-            &ctx,
-            &mut reader,
-            Dimension::D3,
-            vec!(None, None, None)
-        )
-        .is_err());
+        let tensors = BasicStaticLayer::read_layer(&ctx, &mut reader, "layer").unwrap();
+        let names: Vec<String> = tensors.iter().map(|t| t.name()).collect();
+        assert_eq!(names, ["layer.a", "layer.b", "layer.c", "layer.d"]);
+        assert_eq!(reader.stream_position().unwrap(), 1 + 4 + 32 + 8);
+        assert_eq!(ctx.get_tensor("layer.c").unwrap().data(), tensors[2].data());
     }
 
     #[test]
-    fn test_read_layer() {
-        // let ctx = Context::init(MEMORY_SIZE);
-        // let test_file_path = test_file!("resources/model64bytes.bin");
-        // let mut reader = File::open(&test_file_path).expect("Failed to open file");
-
-        // for _ in 0..8 {
-        //     let read_result = BasicStaticLayer::read_layer(
-        //         &ctx,
-        //         &mut reader,
-        //         Dimension::D3,
-        //         vec![Some(2), Some(2), Some(2)],
-        //     );
-        //     assert!(read_result.is_ok());
-        //     let tensor = read_result.unwrap();
-        //     assert_eq!(tensor.nbytes(), 8);
-        // }
-        // assert!(BasicStaticLayer::read_to_tensor(
-        //     &ctx,
-        //     &mut reader,
-        //     Dimension::D3,
-        //     vec!(None, None, None)
-        // )
-        // .is_err());
+    fn test_read_nested_layers() {
+        let ctx = Context::init(MEMORY_SIZE);
+        let test_file_path = test_file!("resources/model.bin");
+        let mut reader = File::open(&test_file_path).expect("Failed to open file");
+
+        let tensors = Stack::read_layer(&ctx, &mut reader, "model").unwrap();
+        assert_eq!(tensors.len(), 6);
+        assert_eq!(tensors[0].name(), "model.layers.0.a");
+        assert_eq!(tensors[5].name(), "model.layers.2.b");
+        assert_eq!(tensors[2].get_i32_1d(0), i32::from(b'5'));
+
+        // Paths too long for ggml get a shortened name, which lookups by
+        // the full path apply as well.
+        let mut reader = File::open(&test_file_path).expect("Failed to open file");
+        let tensors = Stack::read_layer(&ctx, &mut reader, "language_model.encoder").unwrap();
+        let long = "language_model.encoder.layers.2.b";
+        assert!(long.len() > Tensor::MAX_NAME_LEN);
+        assert_eq!(tensors[5].name(), tensor_name(long));
+        assert_ne!(tensors[3].name(), tensors[5].name());
+        assert_eq!(ctx.get_tensor(long).unwrap().data(), tensors[5].data());
+    }
+
+    #[derive(TensorNames)]
+    struct Attention {
+        wq: Tensor,
+        wk: Tensor,
+    }
+
+    #[derive(TensorNames)]
+    struct Block(Attention, Option<Tensor>);
+
+    #[derive(TensorNames)]
+    struct Model {
+        embed: Tensor,
+        layers: Vec<Block>,
+    }
+
+    #[test]
+    fn test_tensor_names() {
+        let ctx = Context::init(MEMORY_SIZE);
+        let layer = |norm: bool| {
+            Block(
+                Attention {
+                    wq: ctx.new_tensor_1d(DataType::F32, 4),
+                    wk: ctx.new_tensor_1d(DataType::F32, 4),
+                },
+                norm.then(|| ctx.new_tensor_1d(DataType::F32, 4)),
+            )
+        };
+        let model = Model {
+            embed: ctx.new_tensor_1d(DataType::F32, 4),
+            layers: vec![layer(false), layer(true)],
+        };
+        model.set_names("model").unwrap();
+
+        assert_eq!(model.embed.name(), "model.embed");
+        assert_eq!(model.layers[1].0.wk.name(), "model.layers.1.0.wk");
+        let norm = ctx.get_tensor("model.layers.1.1").unwrap();
+        assert_eq!(norm.data(), model.layers[1].1.as_ref().unwrap().data());
+        assert!(ctx.get_tensor("model.layers.0.1").is_none());
+
+        let test_file_path = test_file!("resources/model.bin");
+        let mut reader = File::open(&test_file_path).expect("Failed to open file");
+        let name = tensor_path("model", "bias");
+        let bias = EightBitParam::read_to_named_tensor(
+            &ctx,
+            &mut reader,
+            &name,
+            Dimension::D1,
+            vec![None],
+        )
+        .unwrap();
+        assert_eq!(bias.name(), "model.bias");
+        assert!(bias
+            .set_name(&"x".repeat(Tensor::MAX_NAME_LEN + 1))
+            .is_err());
     }
 }
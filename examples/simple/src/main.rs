@@ -53,16 +53,19 @@ mod tests {
     #[test]
     fn test_managed_memory() {
         let mut buffer: ManagedMemory = ManagedMemory([0; MEMORY_SIZE]);
-        let ctx = Context::init_managed(&mut buffer.0);
+        ContextBuilder::new(0)
+            .buffer(&mut buffer.0)
+            .build_scoped(|ctx| {
+                let tensor_a = ctx.new_tensor_1d(DataType::I8, 5);
+                test_i32_value_setting(&tensor_a);
 
-        let tensor_a = ctx.new_tensor_1d(DataType::I8, 5);
-        test_i32_value_setting(&tensor_a);
-
-        let tensor_b = ctx.new_tensor_1d(DataType::F32, 5);
-        test_f32_value_setting(&tensor_b);
+                let tensor_b = ctx.new_tensor_1d(DataType::F32, 5);
+                test_f32_value_setting(&tensor_b);
 
-        println!("{:?}", tensor_a);
-        println!("{:?}", tensor_b);
+                println!("{:?}", tensor_a);
+                println!("{:?}", tensor_b);
+            })
+            .unwrap();
     }
 
     #[test]
@@ -73,4 +76,1061 @@ mod tests {
         let tensor_b = ctx.new_tensor_1d(DataType::F32, 5);
         test_f32_value_setting(&tensor_b);
     }
+
+    #[test]
+    fn test_conv_1d_shapes() {
+        let ctx = Context::init(MEMORY_SIZE * 16);
+        let kernel = ctx.new_tensor_3d(DataType::F16, 3, 4, 8);
+        let input = ctx.new_tensor_2d(DataType::F32, 32, 4);
+
+        let same = ctx.op_conv_1d_ph(&kernel, &input, 1).unwrap();
+        assert_eq!(same.get_ne(), [32, 8, 1, 1]);
+        let halved = ctx.op_conv_1d_ph(&kernel, &input, 2).unwrap();
+        assert_eq!(halved.get_ne(), [16, 8, 1, 1]);
+
+        let mismatched = ctx.new_tensor_2d(DataType::F32, 32, 5);
+        assert!(ctx.op_conv_1d_ph(&kernel, &mismatched, 1).is_err());
+        assert!(ctx.op_conv_1d(&kernel, &input, 3, 1, 1).is_err());
+        let even = ctx.new_tensor_3d(DataType::F16, 4, 4, 8);
+        assert!(ctx.op_conv_1d_ph(&even, &input, 1).is_err());
+
+        let patches = ctx
+            .tensor_from_slice(&[0.0f32; 4 * 4 * 3 * 16], &[4, 4, 3, 16])
+            .unwrap();
+        let image = ctx.new_tensor_3d(DataType::F32, 32, 24, 3);
+        let embedded = ctx
+            .op_conv_2d(&patches, &image, (4, 4), (0, 0), (1, 1))
+            .unwrap();
+        assert_eq!(embedded.get_ne(), [8, 6, 16, 1]);
+        assert!(ctx
+            .op_conv_2d(&patches, &image, (2, 2), (0, 0), (1, 1))
+            .is_err());
+    }
+
+    fn square(src: &[f32], dst: &mut [f32]) {
+        for (d, s) in dst.iter_mut().zip(src) {
+            *d = s * s;
+        }
+    }
+
+    #[test]
+    fn test_rope_modes() {
+        let ctx = Context::init(MEMORY_SIZE);
+        let a = ctx.new_tensor_3d(DataType::F32, 8, 2, 3);
+        a.set_f32(1.0);
+
+        assert!(matches!(
+            ctx.op_rope(&a, 0, 8, RopeMode::Glm),
+            Err(Error::InvalidOperands { op: "rope", .. })
+        ));
+        let glm = ctx.op_rope_with_n_ctx(&a, 0, 8, RopeMode::Glm, 16).unwrap();
+        let neox = ctx.op_rope(&a, 0, 8, RopeMode::NeoX).unwrap();
+        assert_eq!(glm.get_ne(), a.get_ne());
+        assert_eq!(neox.get_ne(), a.get_ne());
+    }
+
+    #[test]
+    fn test_map_unary() {
+        let ctx = Context::init(MEMORY_SIZE * 16);
+        let a = ctx.new_tensor_1d(DataType::F32, 4);
+        for i in 0..4 {
+            assert!(a.set_f32_1d(i, i as f32).is_ok());
+        }
+
+        let b = ctx.op_map_unary(&a, square).unwrap();
+        let c = ctx.op_map_binary(&a, &b, |x, y, dst| {
+            for ((d, x), y) in dst.iter_mut().zip(x).zip(y) {
+                *d = x + y;
+            }
+        });
+
+        let mut graph = ComputationGraph::new(2);
+        graph.build_forward_expand(c.as_ref().unwrap());
+        ctx.graph_compute(&mut graph);
+
+        assert_eq!(b.read_data::<f32>().unwrap(), vec![0.0, 1.0, 4.0, 9.0]);
+        assert_eq!(
+            c.unwrap().read_data::<f32>().unwrap(),
+            vec![0.0, 2.0, 6.0, 12.0]
+        );
+    }
+
+    #[test]
+    fn test_map_op_panics_stay_with_their_computation() {
+        fn compute(f: fn(&[f32], &mut [f32])) -> f32 {
+            let ctx = Context::init(MEMORY_SIZE);
+            let a = ctx.new_tensor_1d(DataType::F32, 4);
+            a.set_f32(3.0);
+            let b = ctx.op_map_unary(&a, f).unwrap();
+            let mut graph = ComputationGraph::new(1);
+            graph.build_forward_expand(&b);
+            ctx.graph_compute(&mut graph);
+            b.get_f32_1d(0)
+        }
+
+        std::thread::scope(|scope| {
+            let failing = scope.spawn(|| {
+                for _ in 0..50 {
+                    let result = std::panic::catch_unwind(|| compute(|_, _| panic!("map op")));
+                    assert!(result.is_err());
+                }
+            });
+            for _ in 0..50 {
+                assert_eq!(compute(square), 9.0);
+            }
+            failing.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn test_operator_overloading() {
+        let ctx = Context::init(MEMORY_SIZE * 16);
+        let a = ctx.new_tensor_1d(DataType::F32, 3);
+        let b = ctx.new_tensor_1d(DataType::F32, 3);
+        let c = ctx.new_tensor_1d(DataType::F32, 3);
+        for i in 0..3 {
+            assert!(a.set_f32_1d(i, i as f32).is_ok());
+            assert!(b.set_f32_1d(i, 2.0).is_ok());
+            assert!(c.set_f32_1d(i, 1.0).is_ok());
+        }
+
+        let expr = -(&a * &b + &c) - &c;
+        let mut graph = ComputationGraph::new(1);
+        graph.build_forward_expand(&expr);
+        ctx.graph_compute(&mut graph);
+
+        assert_eq!(expr.read_data::<f32>().unwrap(), vec![-2.0, -4.0, -6.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "different contexts")]
+    fn test_operator_context_mismatch() {
+        let ctx_a = Context::init(MEMORY_SIZE);
+        let ctx_b = Context::init(MEMORY_SIZE);
+        let a = ctx_a.new_tensor_1d(DataType::F32, 3);
+        let b = ctx_b.new_tensor_1d(DataType::F32, 3);
+        let _ = &a + &b;
+    }
+
+    #[test]
+    fn test_ops_reject_foreign_tensors() {
+        let ctx_a = Context::init(MEMORY_SIZE);
+        let ctx_b = Context::init(MEMORY_SIZE);
+        let a = ctx_a.new_tensor_1d(DataType::F32, 3);
+        let b = ctx_b.new_tensor_1d(DataType::F32, 3);
+
+        assert_eq!(
+            ctx_b.op_add(&a, &b).unwrap_err(),
+            Error::ContextMismatch {
+                op: "add",
+                operand: 0
+            }
+        );
+
+        a.set_f32(1.5);
+        let moved = a.copy_to(&ctx_b).unwrap();
+        assert_eq!(moved.read_data::<f32>().unwrap(), vec![1.5; 3]);
+        assert!(ctx_b.op_add(&moved, &b).is_ok());
+    }
+
+    #[test]
+    fn test_graph_inspection() {
+        let ctx = Context::init(MEMORY_SIZE * 16);
+        let w = ctx.new_tensor_2d(DataType::F32, 4, 3);
+        let x = ctx.new_tensor_1d(DataType::F32, 4);
+        let b = ctx.new_tensor_1d(DataType::F32, 3);
+        let y = w.matmul(&x) + &b;
+
+        let mut graph = ComputationGraph::new(1);
+        graph.build_forward_expand(&y);
+
+        assert_eq!(graph.n_nodes(), 2);
+        assert_eq!(graph.n_leafs(), 3);
+        let ops: Vec<OpKind> = graph.nodes().map(|t| t.op()).collect();
+        assert_eq!(ops, vec![OpKind::MulMat, OpKind::Add]);
+
+        let output = graph.node(1).unwrap();
+        assert_eq!(output.get_ne(), [3, 1, 1, 1]);
+        assert_eq!(output.sources().len(), 2);
+        assert!(graph.leafs().all(|t| t.op() == OpKind::None));
+        assert_eq!(OpKind::MulMat.name(), "mul_mat");
+        assert_eq!(OpKind::from(u32::MAX), OpKind::Other(u32::MAX));
+    }
+
+    #[test]
+    fn test_graph_tensor_owners() {
+        let weights = Context::init(MEMORY_SIZE);
+        let w = weights.new_tensor_1d(DataType::F32, 4);
+        w.set_f32(2.0);
+        let weights = weights.freeze();
+        let shared = weights.share(&w).unwrap();
+
+        let ctx = Context::init(MEMORY_SIZE);
+        let x = ctx.new_tensor_1d(DataType::F32, 4);
+        let y = ctx.op_mul(&shared.tensor(), &x).unwrap();
+        let mut graph = ComputationGraph::new(1);
+        graph.build_forward_expand(&y);
+        let leaf = graph.leafs().find(|t| t.data() == w.data()).unwrap();
+        let source = graph.node(0).unwrap().sources().remove(0);
+        drop(ctx);
+
+        // The weight belongs to the weights context, which is still alive.
+        assert_eq!(leaf.get_f32_1d(0), 2.0);
+        assert_eq!(source.get_f32_1d(3), 2.0);
+    }
+
+    #[test]
+    fn test_graph_to_dot() {
+        let ctx = Context::init(MEMORY_SIZE * 16);
+        let w = ctx.new_tensor_2d(DataType::F32, 4, 3);
+        let x = ctx.new_tensor_1d(DataType::F32, 4);
+        let b = ctx.new_tensor_1d(DataType::F32, 3);
+        let y = w.matmul(&x) + &b;
+
+        let mut graph = ComputationGraph::new(1);
+        graph.build_forward_expand(&y);
+
+        let mut dot = Vec::new();
+        graph.to_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+
+        assert!(dot.starts_with("digraph"));
+        assert!(dot.contains("{mul_mat|3|F32}"));
+        assert!(dot.contains("{none|4 x 3|F32}"));
+        assert_eq!(dot.matches(" -> ").count(), 4);
+    }
+
+    #[test]
+    fn test_profiled_compute() {
+        let ctx = Context::init(MEMORY_SIZE * 16);
+        let w = ctx.new_tensor_2d(DataType::F32, 4, 3);
+        let x = ctx.new_tensor_1d(DataType::F32, 4);
+        let b = ctx.new_tensor_1d(DataType::F32, 3);
+        w.set_f32(1.0);
+        x.set_f32(2.0);
+        b.set_f32(0.5);
+        let y = w.matmul(&x) + &b;
+
+        let mut graph = ComputationGraph::new(2);
+        graph.build_forward_expand(&y);
+        let report = ctx.graph_compute_profiled(&mut graph);
+
+        assert_eq!(y.read_data::<f32>().unwrap(), vec![8.5; 3]);
+        assert_eq!(report.nodes.len(), 2);
+        assert_eq!(report.nodes[0].op, OpKind::MulMat);
+        assert_eq!(report.nodes[0].flops, 2 * 4 * 3);
+        assert_eq!(report.by_op().len(), 2);
+
+        // Re-running reuses the work buffer instead of growing the arena.
+        let used = ctx.used_mem();
+        ctx.graph_compute_profiled(&mut graph);
+        assert_eq!(ctx.used_mem(), used);
+    }
+
+    #[test]
+    fn test_profiled_compute_shares_work_buffer() {
+        let ctx = Context::init(MEMORY_SIZE * 16);
+        // F16 weights make ggml convert the F32 operand in the work buffer.
+        let w = ctx.new_tensor_2d(DataType::F16, 4, 4);
+        let x = ctx.new_tensor_1d(DataType::F32, 4);
+        let b = ctx.new_tensor_1d(DataType::F32, 4);
+        w.set_f32(1.0);
+        x.set_f32(2.0);
+        b.set_f32(0.5);
+        let y = w.matmul(&w.matmul(&x)) + &b;
+
+        let mut graph = ComputationGraph::new(2);
+        graph.build_forward_expand(&y);
+        let used = ctx.used_mem();
+        ctx.graph_compute_profiled(&mut graph);
+        assert_eq!(y.read_data::<f32>().unwrap(), vec![32.5; 4]);
+        let grown = ctx.used_mem() - used;
+        assert!(grown > 0);
+
+        ctx.graph_compute_profiled(&mut graph);
+        ctx.graph_compute_cancellable(&mut graph, &CancellationToken::new())
+            .unwrap();
+        assert_eq!(ctx.used_mem() - used, grown);
+        assert_eq!(y.read_data::<f32>().unwrap(), vec![32.5; 4]);
+    }
+
+    #[test]
+    fn test_graph_export_import() {
+        let ctx = Context::init(MEMORY_SIZE * 16);
+        let w = ctx.new_tensor_2d(DataType::F32, 4, 3);
+        let x = ctx.new_tensor_1d(DataType::F32, 4);
+        let b = ctx.new_tensor_1d(DataType::F32, 3);
+        w.set_f32(1.0);
+        x.set_f32(2.0);
+        b.set_f32(0.5);
+        let y = w.matmul(&x) + &b;
+
+        let mut graph = ComputationGraph::new(1);
+        graph.build_forward_expand(&y);
+        let path = std::env::temp_dir().join("ggml-rs-test-export.ggml");
+        std::fs::write(&path, b"an older export").unwrap();
+        graph.export(&path).unwrap();
+        // The file is written next to `path` and then moved over it.
+        let leftovers = std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter(|e| {
+                let name = e.file_name();
+                let name = name.to_string_lossy();
+                name.starts_with(".ggml-rs-test-export.ggml.") && name.ends_with(".partial")
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+        drop(graph);
+        drop(ctx);
+
+        let mut imported = ComputationGraph::import(&path, 1).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(imported.graph().n_nodes(), 2);
+        imported.compute();
+        let output = imported.graph().node(1).unwrap();
+        assert_eq!(output.read_data::<f32>().unwrap(), vec![8.5; 3]);
+
+        // Leafs belong to the data context, nodes to the eval one.
+        let mismatch = Some(Error::ContextMismatch {
+            op: "sqr",
+            operand: 0,
+        });
+        let leaf = imported.graph().leaf(0).unwrap();
+        assert_eq!(imported.eval_context().op_sqr(&leaf).err(), mismatch);
+        assert_eq!(imported.data_context().op_sqr(&output).err(), mismatch);
+
+        let missing = std::env::temp_dir().join("ggml-rs-test-missing.ggml");
+        let err = ComputationGraph::import(&missing, 1).err().unwrap();
+        assert_eq!(
+            err,
+            Error::GraphFile {
+                path: missing,
+                source: std::io::ErrorKind::NotFound.into()
+            }
+        );
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
+    #[test]
+    fn test_compiled_graph_reruns() {
+        let ctx = Context::init(MEMORY_SIZE * 16);
+        let w = ctx.new_tensor_2d(DataType::F32, 4, 3);
+        let x = ctx.new_tensor_1d(DataType::F32, 4);
+        w.set_f32(1.0);
+        let y = w.matmul(&x);
+
+        let mut compiled = ctx.compile(&[("x", &x)], &[("y", &y)], 1).unwrap();
+        compiled.set_input("x", &[1.0f32, 2.0, 3.0, 4.0]).unwrap();
+        compiled.run();
+        assert_eq!(compiled.output_data::<f32>("y").unwrap(), &[10.0; 3]);
+
+        let used = ctx.used_mem();
+        compiled.set_input("x", &[0.5f32; 4]).unwrap();
+        compiled.run();
+        assert_eq!(compiled.output_data::<f32>("y").unwrap(), &[2.0; 3]);
+        assert_eq!(ctx.used_mem(), used);
+
+        assert_eq!(
+            compiled.set_input("x", &[1i32; 4]),
+            Err(Error::TypeMismatch {
+                expected: DataType::F32,
+                found: DataType::I32
+            })
+        );
+        assert_eq!(
+            compiled.set_input("x", &[1.0f32; 3]),
+            Err(Error::LengthMismatch {
+                expected: 4,
+                found: 3
+            })
+        );
+        assert!(compiled.output_data::<f32>("z").is_err());
+    }
+
+    #[test]
+    fn test_gradients_match_finite_differences() {
+        let ctx = Context::init(MEMORY_SIZE * 16);
+        let a = ctx.new_tensor_1d(DataType::F32, 4);
+        let b = ctx.new_tensor_1d(DataType::F32, 4);
+        a.set_param();
+        for i in 0..4 {
+            a.set_f32_1d(i, 0.5 * i as f32 - 0.7).unwrap();
+            b.set_f32_1d(i, 1.0 + i as f32).unwrap();
+        }
+        let loss = ctx.op_sum(&ctx.op_sqr(&(&a * &b)).unwrap()).unwrap();
+
+        let mut forward = ComputationGraph::new(1);
+        forward.build_forward_expand(&loss);
+        let mut backward = ComputationGraph::build_backward(&mut forward, true).unwrap();
+        backward.reset();
+        loss.grad().unwrap().set_f32(1.0);
+        ctx.graph_compute(&mut backward);
+        let grad = a.grad().unwrap().read_data::<f32>().unwrap().to_vec();
+
+        let eps = 1e-3;
+        for (i, &analytic) in grad.iter().enumerate() {
+            let x = a.get_f32_1d(i as i32);
+            a.set_f32_1d(i, x + eps).unwrap();
+            ctx.graph_compute(&mut forward);
+            let plus = loss.get_f32_1d(0);
+            a.set_f32_1d(i, x - eps).unwrap();
+            ctx.graph_compute(&mut forward);
+            let minus = loss.get_f32_1d(0);
+            a.set_f32_1d(i, x).unwrap();
+
+            let numeric = (plus - minus) / (2.0 * eps);
+            assert!(
+                (numeric - analytic).abs() < 1e-2 * analytic.abs().max(1.0),
+                "d loss / d a[{}]: numeric {} vs autograd {}",
+                i,
+                numeric,
+                analytic
+            );
+        }
+        assert!(b.grad().is_none());
+    }
+
+    #[test]
+    fn test_optimizer_fits_linear_regression() {
+        let ctx = Context::init(MEMORY_SIZE * 64);
+        // Each column of `x` is one sample `(x, 1)`, so `theta = (w, b)`.
+        let n = 8;
+        let x = ctx.new_tensor_2d(DataType::F32, 2, n);
+        let y = ctx.new_tensor_2d(DataType::F32, 1, n);
+        for i in 0..n {
+            let xi = i as f32 / n as f32;
+            x.set_f32_1d(2 * i, xi).unwrap();
+            x.set_f32_1d(2 * i + 1, 1.0).unwrap();
+            y.set_f32_1d(i, 2.0 * xi + 1.0).unwrap();
+        }
+        let theta = ctx.new_tensor_2d(DataType::F32, 2, 1);
+        theta.set_f32(0.0);
+        theta.set_param();
+
+        let error = theta.matmul(&x) - &y;
+        let loss = ctx.op_sum(&ctx.op_sqr(&error).unwrap()).unwrap();
+
+        let outcome = Optimizer::lbfgs(LbfgsParams::default())
+            .minimize(&ctx, &loss)
+            .unwrap();
+        assert_eq!(outcome.result, OptResult::Converged);
+        assert!((theta.get_f32_1d(0) - 2.0).abs() < 1e-2);
+        assert!((theta.get_f32_1d(1) - 1.0).abs() < 1e-2);
+
+        theta.set_f32(0.0);
+        let params = AdamParams {
+            iterations: 50,
+            learning_rate: 0.1,
+            ..Default::default()
+        };
+        let mut reports = Vec::new();
+        let outcome = Optimizer::adam(params)
+            .minimize_with(&ctx, &loss, 10, |p| {
+                reports.push(p.loss);
+                if reports.len() == 3 {
+                    std::ops::ControlFlow::Break(())
+                } else {
+                    std::ops::ControlFlow::Continue(())
+                }
+            })
+            .unwrap();
+        assert_eq!(outcome.result, OptResult::Stopped);
+        assert_eq!(outcome.iterations, 30);
+        assert!(reports[2] < reports[0]);
+    }
+
+    #[test]
+    fn test_repeated_minimize_reuses_optimizer_state() {
+        let ctx = Context::init(MEMORY_SIZE * 64);
+        let target = ctx.new_tensor_1d(DataType::F32, 4);
+        target.set_f32(3.0);
+        let w = ctx.new_tensor_1d(DataType::F32, 4);
+        w.set_f32(0.0);
+        w.set_param();
+        let loss = ctx.op_sum(&ctx.op_sqr(&(&w - &target)).unwrap()).unwrap();
+
+        let params = AdamParams {
+            iterations: 5,
+            learning_rate: 0.1,
+            ..Default::default()
+        };
+        let mut optimizer = Optimizer::adam(params);
+        let mut losses = Vec::new();
+        optimizer.minimize(&ctx, &loss).unwrap();
+        let used = ctx.used_mem();
+        for _ in 0..3 {
+            let outcome = optimizer.minimize(&ctx, &loss).unwrap();
+            assert_eq!(outcome.iterations, 5);
+            assert_eq!(ctx.used_mem(), used);
+            losses.push(outcome.loss);
+        }
+        assert!(losses[2] < losses[0]);
+    }
+
+    #[test]
+    fn test_alternating_thread_counts() {
+        let ctx = Context::init(MEMORY_SIZE * 16);
+        let w = ctx.new_tensor_2d(DataType::F16, 64, 4);
+        let x = ctx.new_tensor_1d(DataType::F32, 64);
+        w.set_f32(0.5);
+        x.set_f32(1.0);
+        // ggml converts `x` to F16 in the work buffer to multiply it with `w`.
+        let y = w.matmul(&x);
+
+        let mut graph = ComputationGraph::new(4);
+        graph.build_forward_expand(&y);
+        ctx.graph_compute(&mut graph);
+        let used = ctx.used_mem();
+        for _ in 0..3 {
+            ctx.graph_compute_with(&mut graph, &ComputeOptions::new().with_threads(2));
+            ctx.graph_compute(&mut graph);
+        }
+        assert_eq!(ctx.used_mem(), used);
+        assert_eq!(y.read_data::<f32>().unwrap(), vec![32.0; 4]);
+    }
+
+    #[test]
+    fn test_compute_threads_per_call() {
+        let ctx = Context::init(MEMORY_SIZE * 16);
+        let w = ctx.new_tensor_2d(DataType::F32, 64, 64);
+        let x = ctx.new_tensor_1d(DataType::F32, 64);
+        w.set_f32(0.5);
+        x.set_f32(1.0);
+        let y = w.matmul(&x);
+
+        let mut graph = ComputationGraph::new(1);
+        graph.build_forward_expand(&y);
+        ctx.graph_compute_with(&mut graph, &ComputeOptions::new().with_threads(4));
+        assert_eq!(graph.n_threads(), 1);
+        assert_eq!(y.read_data::<f32>().unwrap(), vec![32.0; 64]);
+
+        // Going back to fewer threads reuses the larger work buffer.
+        let used = ctx.used_mem();
+        ctx.graph_compute_with(&mut graph, &ComputeOptions::new().with_threads(2));
+        ctx.graph_compute(&mut graph);
+        assert_eq!(ctx.used_mem(), used);
+        assert!(ComputeOptions::new().n_threads() >= 1);
+    }
+
+    #[test]
+    fn test_background_compute() {
+        let mut ctx = Context::init(MEMORY_SIZE * 16);
+        let x = ctx.new_tensor_1d(DataType::F32, 16);
+        x.set_f32(1.0);
+        let mut y = x.share();
+        for _ in 0..32 {
+            y = &y + &x;
+        }
+        let mut graph = ComputationGraph::new(1);
+        graph.build_forward_expand(&y);
+
+        // SAFETY: No tensor of `ctx` is used until the computation is joined.
+        let result = std::thread::scope(|s| {
+            let handle = unsafe {
+                ctx.graph_compute_background(
+                    s,
+                    &mut graph,
+                    ComputeOptions::new(),
+                    CancellationToken::new(),
+                )
+            };
+            handle.join()
+        });
+        assert_eq!(result, Ok(()));
+        assert_eq!(y.get_f32_1d(0), 33.0);
+
+        // A token cancelled up front stops the computation before its first
+        // node, whatever the thread timing.
+        y.set_f32(0.0);
+        let token = CancellationToken::new();
+        token.cancel();
+        // SAFETY: As above.
+        let result = std::thread::scope(|s| {
+            unsafe { ctx.graph_compute_background(s, &mut graph, ComputeOptions::new(), token) }
+                .join()
+        });
+        assert_eq!(result, Err(Error::Cancelled));
+        assert_eq!(y.get_f32_1d(0), 0.0);
+
+        // A cancelled computation leaves the graph usable.
+        ctx.graph_compute(&mut graph);
+        assert_eq!(y.get_f32_1d(0), 33.0);
+    }
+
+    #[test]
+    fn test_cancelled_compute() {
+        let ctx = Context::init(MEMORY_SIZE * 16);
+        let x = ctx.new_tensor_1d(DataType::F32, 16);
+        x.set_f32(1.0);
+        let y = &(&x + &x) + &x;
+        let mut graph = ComputationGraph::new(1);
+        graph.build_forward_expand(&y);
+
+        let token = CancellationToken::new();
+        token.cancel();
+        assert_eq!(
+            ctx.graph_compute_cancellable(&mut graph, &token),
+            Err(Error::Cancelled)
+        );
+        let expired = CancellationToken::with_timeout(std::time::Duration::ZERO);
+        assert_eq!(
+            ctx.graph_compute_cancellable(&mut graph, &expired),
+            Err(Error::Cancelled)
+        );
+
+        let token = CancellationToken::with_timeout(std::time::Duration::from_secs(60));
+        assert_eq!(ctx.graph_compute_cancellable(&mut graph, &token), Ok(()));
+        assert_eq!(y.get_f32_1d(0), 3.0);
+    }
+
+    #[test]
+    fn test_memory_plan() {
+        fn build(ctx: &Context) -> Result<Tensor, Error> {
+            let w = ctx.new_tensor_2d(DataType::F32, 64, 32);
+            let x = ctx.new_tensor_1d(DataType::F32, 64);
+            let b = ctx.new_tensor_1d(DataType::F32, 32);
+            let h = ctx.op_mul_mat(&w, &x)?;
+            ctx.op_add(&h, &b)
+        }
+
+        let report = Context::plan(16, 1, |ctx| build(ctx).map(|_| ())).unwrap();
+        assert_eq!(report.tensors.len(), 5);
+        assert_eq!(report.tensors[0].data_size, 64 * 32 * 4);
+        assert_eq!(report.tensors[3].op, OpKind::MulMat);
+        assert_eq!(report.work_size, 0);
+
+        let ctx = Context::init(report.total());
+        let y = build(&ctx).unwrap();
+        assert!(ctx.used_mem() <= report.total());
+        assert_eq!(y.get_ne(), [32, 1, 1, 1]);
+
+        assert_eq!(
+            Context::plan(4, 1, |ctx| build(ctx).map(|_| ())).unwrap_err(),
+            Error::TooManyTensors { max: 4 }
+        );
+    }
+
+    #[test]
+    fn test_memory_plan_with_operators() {
+        fn build(ctx: &Context) -> Tensor {
+            let w = ctx.new_tensor_2d(DataType::F32, 64, 32);
+            let x = ctx.new_tensor_1d(DataType::F32, 64);
+            let b = ctx.new_tensor_1d(DataType::F32, 32);
+            &w.matmul(&x) + &b
+        }
+
+        let report = Context::plan(16, 1, |ctx| {
+            let y = build(ctx);
+            ComputationGraph::new(1).build_forward_expand(&y);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(report.tensors.len(), 5);
+        assert_eq!(report.tensors[4].op, OpKind::Add);
+
+        let ctx = Context::init(report.total());
+        let y = build(&ctx);
+        for t in y.sources().iter().chain(y.sources()[0].sources().iter()) {
+            t.set_f32(1.0);
+        }
+        let mut graph = ComputationGraph::new(1);
+        graph.build_forward_expand(&y);
+        ctx.graph_compute(&mut graph);
+        assert!(ctx.used_mem() <= report.total());
+        assert_eq!(y.read_data::<f32>().unwrap(), vec![65.0; 32]);
+
+        let overflow = std::panic::catch_unwind(|| {
+            Context::plan(4, 1, |ctx| {
+                build(ctx);
+                Ok(())
+            })
+        });
+        assert!(overflow.is_err());
+    }
+
+    #[test]
+    fn test_memory_plan_includes_work_buffer() {
+        fn build(ctx: &Context) -> Result<Tensor, Error> {
+            let w = ctx.new_tensor_2d(DataType::F16, 64, 32);
+            let x = ctx.new_tensor_1d(DataType::F32, 64);
+            ctx.op_mul_mat(&w, &x)
+        }
+
+        // ggml converts `x` to F16, padded by a cache line per extra thread.
+        let report = Context::plan(8, 2, |ctx| build(ctx).map(|_| ())).unwrap();
+        assert_eq!(report.work_size, 64 * 2 + 64);
+
+        let ctx = Context::init(report.total());
+        let y = build(&ctx).unwrap();
+        let mut graph = ComputationGraph::new(2);
+        graph.build_forward_expand(&y);
+        ctx.graph_compute(&mut graph);
+        assert!(ctx.used_mem() <= report.total());
+    }
+
+    #[test]
+    fn test_scratch_buffer() {
+        let mut ctx = Context::init(MEMORY_SIZE * 16);
+        let w = ctx.new_tensor_2d(DataType::F32, 16, 16);
+        let x = ctx.new_tensor_1d(DataType::F32, 16);
+        w.set_f32(0.25);
+        x.set_f32(1.0);
+
+        let mut scratch = vec![0u8; MEMORY_SIZE * 4];
+        let mut escaped = None;
+        for _ in 0..4 {
+            let used = ctx.used_mem();
+            let sum = ctx.with_scratch(&mut scratch, |scope| {
+                let h = scope.op_mul_mat(&w, &x).unwrap() + &x;
+                let mut graph = ComputationGraph::new(1);
+                graph.build_forward_expand(&h);
+                scope.graph_compute(&mut graph);
+                let sum: f32 = h.read_data::<f32>().unwrap().iter().sum();
+                escaped = Some(h);
+                sum
+            });
+            assert_eq!(sum, 80.0);
+            // Only the bookkeeping of the intermediates lands in the arena.
+            assert!(ctx.used_mem() - used < 16 * 16 * 4);
+        }
+
+        let escaped = escaped.unwrap();
+        let result =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| escaped.get_f32_1d(0)));
+        assert!(result.is_err());
+        assert_eq!(ctx.op_sqr(&escaped).err(), Some(Error::ContextDropped));
+    }
+
+    #[test]
+    #[should_panic(expected = "context of its tensors was dropped")]
+    fn test_graph_with_ended_scratch_nodes() {
+        let mut ctx = Context::init(MEMORY_SIZE * 16);
+        let x = ctx.new_tensor_1d(DataType::F32, 16);
+        let mut graph = ComputationGraph::new(1);
+        graph.build_forward_expand(&(&x + &x));
+
+        let mut scratch = vec![0u8; MEMORY_SIZE];
+        ctx.with_scratch(&mut scratch, |scope| {
+            graph.build_forward_expand(&scope.op_sqr(&x).unwrap());
+        });
+        ctx.graph_compute(&mut graph);
+    }
+
+    #[test]
+    fn test_no_alloc_attached_data() {
+        let ctx = ContextBuilder::new(MEMORY_SIZE)
+            .no_alloc(true)
+            .build()
+            .unwrap();
+        let a = ctx.new_tensor_1d(DataType::F32, 4);
+        let used = ctx.used_mem();
+
+        let values = [1.0f32, 2.0, 3.0, 4.0];
+        assert_eq!(
+            ctx.attach_data(&a, vec![0f32; 2]),
+            Err(Error::BufferTooSmall {
+                needed: 16,
+                found: 8
+            })
+        );
+        assert_eq!(
+            ctx.attach_data(&a, vec![0i32; 4]),
+            Err(Error::TypeMismatch {
+                expected: DataType::F32,
+                found: DataType::I32
+            })
+        );
+        ctx.attach_data(&a, values.to_vec()).unwrap();
+        assert_eq!(a.read_data::<f32>().unwrap(), &values);
+        assert_eq!(ctx.used_mem(), used);
+
+        let mut borrowed = [0f32; 4];
+        let buffer =
+            unsafe { std::slice::from_raw_parts_mut(borrowed.as_mut_ptr() as *mut u8, 16) };
+        unsafe { a.set_data_from(buffer).unwrap() };
+        a.set_f32(0.5);
+        assert_eq!(borrowed, [0.5; 4]);
+    }
+
+    #[test]
+    fn test_context_builder_buffers() {
+        let mut buffer = ManagedMemory([0; MEMORY_SIZE]);
+        assert_eq!(
+            ContextBuilder::new(0)
+                .buffer(&mut buffer.0[1..])
+                .build_scoped(|_| ())
+                .err(),
+            Some(Error::Misaligned {
+                required: ARENA_ALIGN
+            })
+        );
+
+        let mut data = ManagedMemory([0; MEMORY_SIZE]);
+        let a = ContextBuilder::new(0)
+            .buffer(&mut buffer.0)
+            .build_scoped(|ctx| {
+                let a = ctx.new_tensor_1d(DataType::F32, 4);
+                ctx.attach_borrowed(&a, &mut data.0).unwrap();
+                a.set_f32(2.0);
+                a
+            })
+            .unwrap();
+        assert_eq!(&data.0[..4], &2.0f32.to_ne_bytes());
+        // The scope dropped the context, so the tensor no longer reaches
+        // either buffer.
+        assert_eq!(a.to_vec::<f32>(), Err(Error::ContextDropped));
+
+        // An owned buffer is used from its first aligned byte on, however
+        // the allocator aligned it.
+        let ctx = ContextBuilder::new(0)
+            .owned_buffer(vec![0u8; MEMORY_SIZE])
+            .build()
+            .unwrap();
+        let b = ctx.new_tensor_1d(DataType::I32, 3);
+        b.set_i32(7);
+        assert_eq!(b.read_data::<i32>().unwrap(), &[7, 7, 7]);
+    }
+
+    #[test]
+    fn test_shared_weights_context() {
+        let weights = Context::init(MEMORY_SIZE);
+        let w = weights.new_tensor_1d(DataType::F32, 4);
+        w.set_f32(2.0);
+        let weights = std::sync::Arc::new(weights.freeze());
+        let shared = weights.share(&w).unwrap();
+
+        let handles: Vec<_> = (1..=3)
+            .map(|i| {
+                let (weights, shared) = (weights.clone(), shared.clone());
+                std::thread::spawn(move || {
+                    let ctx = Context::init(MEMORY_SIZE);
+                    let x = ctx.new_tensor_1d(DataType::F32, 4);
+                    x.set_f32(i as f32);
+                    let y = ctx.op_mul(&shared.tensor(), &x).unwrap();
+                    let mut graph = ComputationGraph::new(1);
+                    graph.build_forward_expand(&y);
+                    ctx.graph_compute(&mut graph);
+                    drop(weights);
+                    y.get_f32_1d(0)
+                })
+            })
+            .collect();
+        let results: Vec<f32> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(results, [2.0, 4.0, 6.0]);
+
+        // Handles from before freezing can still read, but not write.
+        assert_eq!(w.get_f32_1d(3), 2.0);
+        let write = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| w.set_f32(1.0)));
+        assert!(write.is_err());
+    }
+
+    #[test]
+    fn test_frozen_graph_leafs_refuse_writes() {
+        let weights = Context::init(MEMORY_SIZE);
+        let w = weights.new_tensor_1d(DataType::F32, 4);
+        w.set_name("w").unwrap();
+        let weights = weights.freeze();
+        let shared = weights.share(&w).unwrap();
+
+        let ctx = Context::init(MEMORY_SIZE);
+        let x = ctx.new_tensor_1d(DataType::F32, 4);
+        let y = ctx.op_mul(&shared.tensor(), &x).unwrap();
+        let mut graph = ComputationGraph::new(1);
+        graph.build_forward_expand(&y);
+
+        let data = [1.0f32; 4];
+        let leaf = graph.leafs().find(|t| t.name() == "w").unwrap();
+        assert_eq!(leaf.copy_from_slice(&data), Err(Error::Frozen));
+        let source = y.sources().remove(0);
+        assert_eq!(source.copy_from_slice(&data), Err(Error::Frozen));
+        let named = graph.get_tensor("w").unwrap();
+        assert_eq!(named.copy_from_slice(&data), Err(Error::Frozen));
+        assert_eq!(x.copy_from_slice(&data), Ok(()));
+    }
+
+    #[test]
+    fn test_views_of_frozen_tensors_refuse_writes() {
+        let weights = Context::init(MEMORY_SIZE);
+        let w = weights.new_tensor_1d(DataType::F32, 8);
+        w.set_f32(1.0);
+        let weights = weights.freeze();
+        let w = weights.share(&w).unwrap().tensor();
+
+        let ctx = Context::init(MEMORY_SIZE);
+        let data = [2.0f32; 4];
+        let view = ctx.op_view_1d(&w, 4, 0).unwrap();
+        assert_eq!(view.copy_from_slice(&data), Err(Error::Frozen));
+        let reshaped = ctx.op_reshape_3d(&view, 2, 2, 1).unwrap();
+        assert_eq!(reshaped.copy_from_slice(&data), Err(Error::Frozen));
+
+        let x = ctx.new_tensor_1d(DataType::F32, 4);
+        x.set_f32(3.0);
+        for destination in [&w, &view] {
+            assert!(matches!(
+                ctx.op_cpy(&x, destination),
+                Err(Error::InvalidOperands { op: "cpy", .. })
+            ));
+        }
+        let own = ctx.new_tensor_1d(DataType::F32, 4);
+        assert!(ctx.op_cpy(&view, &own).is_ok());
+
+        // The views keep the frozen data readable.
+        drop(weights);
+        assert_eq!(view.to_vec::<f32>().unwrap(), vec![1.0; 4]);
+    }
+
+    #[test]
+    fn test_tensor_display() {
+        let ctx = Context::init(MEMORY_SIZE);
+        let a = ctx.new_tensor_2d(DataType::F32, 3, 2);
+        for i in 0..6 {
+            a.set_f32_1d(i, i as f32).unwrap();
+        }
+        a.set_name("a").unwrap();
+        assert_eq!(
+            format!("{:.1}", a),
+            "\"a\" F32 [3, 2] strides [4, 12]\n[[0.0, 1.0, 2.0],\n [3.0, 4.0, 5.0]]"
+        );
+
+        let big = ctx.new_tensor_1d(DataType::I32, 2000);
+        big.set_i32(7);
+        let text = big.display().edge_items(2).to_string();
+        assert_eq!(text, "I32 [2000] strides [4]\n[7, 7, ..., 7, 7]");
+    }
+
+    #[test]
+    fn test_tensor_stats_and_finite_check() {
+        let ctx = Context::init(MEMORY_SIZE);
+        let a = ctx.new_tensor_1d(DataType::F32, 4);
+        for (i, value) in [1.0, 2.0, 3.0, f32::NAN].into_iter().enumerate() {
+            a.set_f32_1d(i, value).unwrap();
+        }
+        let stats = a.stats();
+        assert_eq!((stats.count, stats.nan_count, stats.inf_count), (4, 1, 0));
+        assert_eq!((stats.min, stats.max, stats.mean), (1.0, 3.0, 2.0));
+        assert!((stats.std - (2.0f64 / 3.0).sqrt()).abs() < 1e-9);
+
+        a.set_f32_1d(3, 1e30).unwrap();
+        let b = &a + &a;
+        let c = &b * &b;
+        c.set_name("c").unwrap();
+        let mut graph = ComputationGraph::new(1);
+        graph.build_forward_expand(&c);
+        ctx.graph_compute(&mut graph);
+        assert!(b.stats().is_finite());
+        match graph.check_finite() {
+            Err(Error::NonFinite {
+                node,
+                name,
+                inf_count,
+                ..
+            }) => assert_eq!((node, name.as_str(), inf_count), (1, "c", 1)),
+            other => panic!("expected a non-finite node, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_tensor_from_slice() {
+        let ctx = Context::init(MEMORY_SIZE);
+        let a = ctx
+            .tensor_from_slice(&[1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0], &[3, 2])
+            .unwrap();
+        assert_eq!(a.get_ne(), [3, 2, 1, 1]);
+        assert_eq!(a.to_vec::<f32>().unwrap(), [1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+
+        let t = ctx.op_permute(&a, 1, 0, 2, 3).unwrap();
+        assert!(!t.is_contiguous());
+        assert_eq!(t.to_vec::<f32>().unwrap(), [1.0, 4.0, 2.0, 5.0, 3.0, 6.0]);
+
+        a.copy_from_slice(&[0.5f32; 6]).unwrap();
+        assert_eq!(a.get_f32_1d(5), 0.5);
+        assert_eq!(
+            a.copy_from_slice(&[1i32; 6]),
+            Err(Error::TypeMismatch {
+                expected: DataType::F32,
+                found: DataType::I32
+            })
+        );
+        assert_eq!(
+            ctx.tensor_from_slice(&[1i32, 2, 3], &[2, 2]).err(),
+            Some(Error::LengthMismatch {
+                expected: 4,
+                found: 3
+            })
+        );
+        assert!(matches!(
+            ctx.tensor_from_slice::<f32>(&[], &[i32::MAX as usize + 1, 0]),
+            Err(Error::InvalidOperands { .. })
+        ));
+
+        let no_alloc = ContextBuilder::new(MEMORY_SIZE)
+            .no_alloc(true)
+            .build()
+            .unwrap();
+        let empty = no_alloc.new_tensor_1d(DataType::F32, 2);
+        assert_eq!(empty.copy_from_slice(&[1.0f32; 2]), Err(Error::NoData));
+        assert_eq!(empty.to_vec::<f32>(), Err(Error::NoData));
+    }
+
+    #[test]
+    #[cfg(feature = "ndarray")]
+    fn test_ndarray_conversions() {
+        use ggml_rs::ndarray::{arr2, Array2};
+
+        let ctx = Context::init(MEMORY_SIZE);
+        let array = arr2(&[[1.0f32, 2.0, 3.0], [4.0, 5.0, 6.0]]);
+        let a = ctx.tensor_from_array(&array).unwrap();
+        assert_eq!(a.get_ne(), [3, 2, 1, 1]);
+        assert_eq!(a.as_array_view::<f32>().unwrap(), array.view().into_dyn());
+
+        // Transposed arrays and permuted tensors go through their strides.
+        let t = ctx.tensor_from_array(&array.t()).unwrap();
+        assert_eq!(t.get_ne(), [2, 3, 1, 1]);
+        let p = ctx.op_permute(&a, 1, 0, 2, 3).unwrap();
+        assert_eq!(
+            p.as_array_view::<f32>().unwrap(),
+            t.as_array_view::<f32>().unwrap()
+        );
+
+        let ints: Array2<i32> = Array2::zeros((2, 2));
+        assert!(ctx
+            .tensor_from_array(&ints)
+            .unwrap()
+            .as_array_view::<f32>()
+            .is_err());
+
+        let no_alloc = ContextBuilder::new(MEMORY_SIZE)
+            .no_alloc(true)
+            .build()
+            .unwrap();
+        let empty = no_alloc.new_tensor_1d(DataType::F32, 2);
+        assert_eq!(empty.as_array_view::<f32>().err(), Some(Error::NoData));
+    }
+
+    #[test]
+    fn test_allclose() {
+        let ctx = Context::init(MEMORY_SIZE);
+        let a = ctx
+            .tensor_from_slice(&[1.0f32, 2.0, 3.0, 4.0], &[2, 2])
+            .unwrap();
+        let b = &a * &a;
+        let mut graph = ComputationGraph::new(1);
+        graph.build_forward_expand(&b);
+        ctx.graph_compute(&mut graph);
+
+        assert_allclose!(b, [1.0f32, 4.0, 9.0, 16.0]);
+        let ints = ctx.tensor_from_slice(&[1, 4, 9, 16], &[2, 2]).unwrap();
+        assert!(b.allclose(&ints, 0.0, 0.0));
+        assert!(!b.allclose(&[1.0f32, 4.0, 9.0], 0.0, 0.0));
+
+        let comparison = b.compare(&[1.0f32, 4.0, 9.5, 16.0], 1e-5, 1e-8).unwrap();
+        assert_eq!(comparison.mismatches, 1);
+        assert_eq!(comparison.first_mismatch.unwrap().index, [0, 1, 0, 0]);
+        assert_eq!(comparison.max_abs_error, 0.5);
+        assert_allclose!(b, [1.0f32, 4.0, 9.5, 16.0], rtol = 0.1, atol = 0.0);
+
+        let failure = std::panic::catch_unwind(|| assert_allclose!(a, [1.0f32, 2.0, 3.0, 5.0]));
+        assert!(failure.is_err());
+    }
 }
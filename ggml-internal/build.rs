@@ -1,13 +1,157 @@
 extern crate bindgen;
 
 use std::env;
+use std::fs;
 use std::path::PathBuf;
 
+const HEADER: &str = "ggml/include/ggml/ggml.h";
+
+/// ggml is pinned to the last revision before `ggml_cplan` (early July 2023):
+/// later revisions move the thread count and work buffer out of
+/// `ggml_cgraph`, which the bindings still use. These are the functions the
+/// bindings call, with their number of parameters at that revision; the
+/// build stops early if the checkout in `ggml/` declares them differently.
+const PINNED_FUNCTIONS: &[(&str, usize)] = &[
+    ("ggml_init", 1),
+    ("ggml_graph_compute", 2),
+    ("ggml_get_mem_buffer", 1),
+    ("ggml_get_mem_size", 1),
+    ("ggml_graph_export", 2),
+    ("ggml_graph_import", 3),
+    ("ggml_graph_get_tensor", 2),
+    ("ggml_opt_init", 4),
+    ("ggml_opt_resume_g", 5),
+    ("ggml_internal_get_quantize_fn", 1),
+    ("ggml_rope", 6),
+    ("ggml_alibi", 5),
+    ("ggml_conv_1d_s1_ph", 3),
+    ("ggml_conv_1d_s2_ph", 3),
+    ("ggml_conv_2d_sk_p0", 3),
+];
+
+/// Fields of ggml's structs the bindings read or write directly.
+const PINNED_FIELDS: &[(&str, &[&str])] = &[
+    ("ggml_cgraph", &["n_threads", "work_size", "work"]),
+    ("ggml_tensor", &["src0", "src1", "opt", "n_tasks", "name"]),
+    ("ggml_init_params", &["mem_size", "mem_buffer", "no_alloc"]),
+];
+
+/// Enum values the bindings rely on beyond the generated constants: tensors
+/// keep their type as a number in model files and exported graphs.
+const PINNED_VALUES: &[(&str, u32)] = &[
+    ("GGML_TYPE_Q4_0", 2),
+    ("GGML_TYPE_Q4_1", 3),
+    ("GGML_TYPE_Q5_0", 6),
+    ("GGML_TYPE_Q5_1", 7),
+    ("GGML_TYPE_Q8_0", 8),
+];
+
+/// Declarations added after the pinned revision.
+const NEWER_API: &[&str] = &["struct ggml_cplan"];
+
+/// Strips comments and collapses whitespace, so that declarations can be
+/// found regardless of how they are formatted.
+fn normalize(header: &str) -> String {
+    let mut code = String::with_capacity(header.len());
+    let mut rest = header;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("//") {
+            rest = after.find('\n').map_or("", |end| &after[end..]);
+        } else if let Some(after) = rest.strip_prefix("/*") {
+            rest = after.find("*/").map_or("", |end| &after[end + 2..]);
+        } else {
+            let c = rest.chars().next().unwrap();
+            code.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    code.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Number of parameters `function` is declared with, if it is declared.
+fn parameter_count(code: &str, function: &str) -> Option<usize> {
+    let start = code.match_indices(function).map(|(i, _)| i).find(|&i| {
+        let before = code[..i].chars().next_back();
+        let after = code[i + function.len()..].trim_start();
+        !before.is_some_and(|c| c.is_alphanumeric() || c == '_') && after.starts_with('(')
+    })?;
+    let params = &code[start + function.len()..];
+    let params = &params[params.find('(')? + 1..params.find(')')?];
+    Some(match params.trim() {
+        "" | "void" => 0,
+        params => params.split(',').count(),
+    })
+}
+
+fn check_pinned_api(header: &str) {
+    let code = normalize(header);
+    let mismatch = |what: String| -> ! {
+        panic!(
+            "{} does not match the pinned ggml revision: {}. Check out the ggml revision \
+             described in build.rs.",
+            HEADER, what
+        )
+    };
+
+    for &(function, expected) in PINNED_FUNCTIONS {
+        match parameter_count(&code, function) {
+            None => mismatch(format!("`{}` is not declared", function)),
+            Some(found) if found != expected => mismatch(format!(
+                "`{}` takes {} parameters instead of {}",
+                function, found, expected
+            )),
+            Some(_) => {}
+        }
+    }
+    for &(name, fields) in PINNED_FIELDS {
+        let body = code
+            .find(&format!("struct {} {{", name))
+            .map(|start| &code[start..])
+            .and_then(|s| s.find("};").map(|end| &s[..end]))
+            .unwrap_or_else(|| mismatch(format!("`struct {}` is not declared", name)));
+        for field in fields.iter() {
+            let declared = body
+                .split(';')
+                .filter_map(|member| member.split(['[', ':']).next())
+                .any(|member| {
+                    member.trim_end().ends_with(&format!(" {}", field))
+                        || member.trim_end().ends_with(&format!("*{}", field))
+                });
+            if !declared {
+                mismatch(format!("`struct {}` has no field `{}`", name, field));
+            }
+        }
+    }
+    for &(name, expected) in PINNED_VALUES {
+        let value = code
+            .split(['{', ',', '}'])
+            .find_map(|item| item.trim().strip_prefix(name)?.trim().strip_prefix('='))
+            .unwrap_or_else(|| mismatch(format!("`{}` is not declared with a value", name)));
+        if value.trim() != expected.to_string() {
+            mismatch(format!(
+                "`{}` is {} instead of {}",
+                name,
+                value.trim(),
+                expected
+            ));
+        }
+    }
+    for &declaration in NEWER_API {
+        if code.contains(declaration) {
+            mismatch(format!("it declares `{}`, which is newer", declaration));
+        }
+    }
+}
+
 pub fn main() {
+    let header = fs::read_to_string(HEADER)
+        .unwrap_or_else(|e| panic!("Could not read {}, check out ggml first: {}", HEADER, e));
+    check_pinned_api(&header);
+
     let dst = cmake::Config::new("ggml").build();
 
     let bindings = bindgen::Builder::default()
-        .header("ggml/include/ggml/ggml.h")
+        .header(HEADER)
         .parse_callbacks(Box::new(bindgen::CargoCallbacks))
         .generate()
         .expect("Unable to generate bindings");
@@ -3,7 +3,7 @@
 extern crate proc_macro;
 use proc_macro2;
 use quote::quote;
-use syn::{parse2, parse_macro_input, Attribute, DeriveInput, Lit, MetaNameValue};
+use syn::{parse2, parse_macro_input, Attribute, Data, DeriveInput, Fields, Lit, MetaNameValue};
 
 fn filter_tensor_params_attributes(attrs: &[Attribute]) -> Vec<&Attribute> {
     attrs
@@ -128,6 +128,19 @@ fn derive_model_io_impl(input: proc_macro2::TokenStream) -> proc_macro2::TokenSt
                 Ok(())
             }
         }
+
+        impl ggml_rs::io::StaticLayer for #name {
+            fn read_layer<R: std::io::Read>(
+                ctx: &ggml_rs::Context,
+                reader: &mut R,
+                path: &str,
+            ) -> Result<Vec<ggml_rs::Tensor>, ()> {
+                let shape = vec![None; 3];
+                let tensor = <Self as ggml_rs::io::ModelIO>::read_to_tensor(ctx, reader, #ggml_dim, shape)?;
+                tensor.set_name(&ggml_rs::io::tensor_name(path)).map_err(|_| ())?;
+                Ok(vec![tensor])
+            }
+        }
     }
 }
 
@@ -136,6 +149,109 @@ pub fn derive_model_io(input: proc_macro::TokenStream) -> proc_macro::TokenStrea
     derive_model_io_impl(input.into()).into()
 }
 
+fn derive_tensor_names_impl(input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let input: DeriveInput = parse2(input).unwrap();
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => panic!("TensorNames can only be derived for structs."),
+    };
+    let set_names = match fields {
+        Fields::Named(fields) => fields
+            .named
+            .iter()
+            .map(|field| {
+                let ident = field.ident.as_ref().unwrap();
+                let segment = ident.to_string();
+                quote! {
+                    ggml_rs::io::TensorNames::set_names(
+                        &self.#ident,
+                        &ggml_rs::io::tensor_path(path, #segment),
+                    )?;
+                }
+            })
+            .collect::<Vec<_>>(),
+        Fields::Unnamed(fields) => (0..fields.unnamed.len())
+            .map(|i| {
+                let index = syn::Index::from(i);
+                let segment = i.to_string();
+                quote! {
+                    ggml_rs::io::TensorNames::set_names(
+                        &self.#index,
+                        &ggml_rs::io::tensor_path(path, #segment),
+                    )?;
+                }
+            })
+            .collect(),
+        Fields::Unit => Vec::new(),
+    };
+
+    quote! {
+        impl #impl_generics ggml_rs::io::TensorNames for #name #ty_generics #where_clause {
+            fn set_names(&self, path: &str) -> Result<(), ggml_rs::Error> {
+                #(#set_names)*
+                Ok(())
+            }
+        }
+    }
+}
+
+#[proc_macro_derive(TensorNames)]
+pub fn derive_tensor_names(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    derive_tensor_names_impl(input.into()).into()
+}
+
+fn derive_static_layer_impl(input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let input: DeriveInput = parse2(input).unwrap();
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => panic!("StaticLayer can only be derived for structs."),
+    };
+    let segments: Vec<String> = match fields {
+        Fields::Named(fields) => fields
+            .named
+            .iter()
+            .map(|field| field.ident.as_ref().unwrap().to_string())
+            .collect(),
+        Fields::Unnamed(fields) => (0..fields.unnamed.len()).map(|i| i.to_string()).collect(),
+        Fields::Unit => Vec::new(),
+    };
+    let read_fields = fields.iter().zip(&segments).map(|(field, segment)| {
+        let ty = &field.ty;
+        quote! {
+            tensors.extend(<#ty as ggml_rs::io::StaticLayer>::read_layer(
+                ctx,
+                reader,
+                &ggml_rs::io::tensor_path(path, #segment),
+            )?);
+        }
+    });
+
+    quote! {
+        impl #impl_generics ggml_rs::io::StaticLayer for #name #ty_generics #where_clause {
+            fn read_layer<R: std::io::Read>(
+                ctx: &ggml_rs::Context,
+                reader: &mut R,
+                path: &str,
+            ) -> Result<Vec<ggml_rs::Tensor>, ()> {
+                let mut tensors = Vec::new();
+                #(#read_fields)*
+                Ok(tensors)
+            }
+        }
+    }
+}
+
+#[proc_macro_derive(StaticLayer)]
+pub fn derive_static_layer(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    derive_static_layer_impl(input.into()).into()
+}
+
 fn fetch_static_key_value_pair(attr_terms: &Vec<String>, key: &str) -> Option<String> {
     let pos = attr_terms
         .windows(3)
@@ -0,0 +1,61 @@
+use crate::context::Context;
+use crate::error::Error;
+use crate::slice::check_layout;
+use crate::tensor::{Element, Tensor};
+use ndarray::{ArrayBase, ArrayViewD, Data, IxDyn, ShapeBuilder};
+
+impl Tensor {
+    /// Views the tensor's data as an `ndarray` array without copying it.
+    ///
+    /// ggml lists axes innermost first while `ndarray` lists them outermost
+    /// first, so the axes are reversed: a tensor with `get_ne()` of
+    /// `[3, 2, 1, 1]` becomes an array of shape `[2, 3]`. Strides are taken
+    /// from [`Tensor::get_nb`], so permuted and transposed views work too.
+    /// Tensors without data, e.g. in a [`crate::ContextBuilder::no_alloc`]
+    /// context, return [`Error::NoData`].
+    pub fn as_array_view<T: Element>(&self) -> Result<ArrayViewD<'_, T>, Error> {
+        let _alive = self.ctx.upgrade().ok_or(Error::ContextDropped)?;
+        check_layout::<T>(self, self.nelements())?;
+        let n_dims = self.n_dims();
+        let size = std::mem::size_of::<T>();
+        let ne = self.get_ne();
+        let nb = self.get_nb();
+        let shape: Vec<usize> = ne[..n_dims].iter().rev().map(|&n| n as usize).collect();
+        let strides: Vec<usize> = nb[..n_dims].iter().rev().map(|&n| n / size).collect();
+        if nb[..n_dims].iter().any(|&n| n % size != 0) {
+            return Err(Error::Misaligned { required: size });
+        }
+        let data = self.data() as *const T;
+        if data.align_offset(std::mem::align_of::<T>()) != 0 {
+            return Err(Error::Misaligned {
+                required: std::mem::align_of::<T>(),
+            });
+        }
+        // SAFETY: The type was checked above and every element the strides
+        // reach lies within the data, which lives as long as the tensor's
+        // context and is only written through the tensor.
+        Ok(unsafe { ArrayViewD::from_shape_ptr(IxDyn(&shape).strides(IxDyn(&strides)), data) })
+    }
+}
+
+impl Context<'_> {
+    /// Creates a tensor holding a copy of `array`, with the axes reversed
+    /// into ggml's order as described at [`Tensor::as_array_view`]. Arrays
+    /// of any memory layout are accepted; zero-dimensional arrays become a
+    /// tensor of one element.
+    pub fn tensor_from_array<T, S, D>(&self, array: &ArrayBase<S, D>) -> Result<Tensor, Error>
+    where
+        T: Element,
+        S: Data<Elem = T>,
+        D: ndarray::Dimension,
+    {
+        let mut shape: Vec<usize> = array.shape().iter().rev().copied().collect();
+        if shape.is_empty() {
+            shape.push(1);
+        }
+        // Iterating in logical order yields the outermost axis slowest,
+        // which is ggml's layout once the axes are reversed.
+        let data: Vec<T> = array.iter().copied().collect();
+        self.tensor_from_slice(&data, &shape)
+    }
+}
@@ -0,0 +1,208 @@
+use crate::error::Error;
+use crate::tensor::{Element, Tensor};
+use std::fmt;
+
+/// Values a tensor can be compared against with [`Tensor::allclose`] and
+/// [`Tensor::compare`]: another tensor of any type, or a Rust slice, array
+/// or vector of elements in ggml's order, innermost axis first.
+pub trait Expected {
+    /// The shape to check against, if the values have one.
+    fn expected_shape(&self) -> Option<[i32; 4]>;
+    fn expected_values(&self) -> Vec<f64>;
+}
+
+impl Expected for Tensor {
+    fn expected_shape(&self) -> Option<[i32; 4]> {
+        Some(self.get_ne())
+    }
+
+    fn expected_values(&self) -> Vec<f64> {
+        let mut values = Vec::with_capacity(self.nelements());
+        self.for_each_row(|row| values.extend(row.iter().map(|&v| v as f64)));
+        values
+    }
+}
+
+impl<T: Element + Into<f64>> Expected for [T] {
+    fn expected_shape(&self) -> Option<[i32; 4]> {
+        None
+    }
+
+    fn expected_values(&self) -> Vec<f64> {
+        self.iter().map(|&v| v.into()).collect()
+    }
+}
+
+impl<T: Element + Into<f64>, const N: usize> Expected for [T; N] {
+    fn expected_shape(&self) -> Option<[i32; 4]> {
+        None
+    }
+
+    fn expected_values(&self) -> Vec<f64> {
+        self.as_slice().expected_values()
+    }
+}
+
+impl<T: Element + Into<f64>> Expected for Vec<T> {
+    fn expected_shape(&self) -> Option<[i32; 4]> {
+        None
+    }
+
+    fn expected_values(&self) -> Vec<f64> {
+        self.as_slice().expected_values()
+    }
+}
+
+/// The first element found out of tolerance by [`Tensor::compare`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mismatch {
+    /// Position of the element, innermost axis first like
+    /// [`Tensor::get_ne`].
+    pub index: [usize; 4],
+    pub actual: f64,
+    pub expected: f64,
+}
+
+/// How far a tensor is from the expected values, see [`Tensor::compare`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Comparison {
+    /// Number of elements out of tolerance.
+    pub mismatches: usize,
+    pub first_mismatch: Option<Mismatch>,
+    /// Largest absolute error over all elements.
+    pub max_abs_error: f64,
+    /// Largest error relative to the magnitude of the expected value, over
+    /// all elements with a non-zero expected value.
+    pub max_rel_error: f64,
+}
+
+impl Comparison {
+    pub fn is_close(&self) -> bool {
+        self.mismatches == 0
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(m) = self.first_mismatch {
+            write!(
+                f,
+                "{} elements differ, first at index {:?}: {} != {} (expected); ",
+                self.mismatches, m.index, m.actual, m.expected
+            )?;
+        }
+        write!(
+            f,
+            "max absolute error {}, max relative error {}",
+            self.max_abs_error, self.max_rel_error
+        )
+    }
+}
+
+impl Tensor {
+    /// Whether every element is within `atol + rtol * |expected|` of the
+    /// expected one, like NumPy's `allclose`. NaNs are never close. Returns
+    /// `false` if the shapes or element counts differ.
+    pub fn allclose<E: Expected + ?Sized>(&self, expected: &E, rtol: f64, atol: f64) -> bool {
+        self.compare(expected, rtol, atol)
+            .is_ok_and(|comparison| comparison.is_close())
+    }
+
+    /// Compares the tensor element-wise with `expected` as in
+    /// [`Tensor::allclose`], reporting where and by how much they differ.
+    /// Values of any type are converted to `f64` first, dequantizing
+    /// quantized tensors.
+    pub fn compare<E: Expected + ?Sized>(
+        &self,
+        expected: &E,
+        rtol: f64,
+        atol: f64,
+    ) -> Result<Comparison, Error> {
+        let ne = self.get_ne().map(|n| n as usize);
+        if expected
+            .expected_shape()
+            .is_some_and(|shape| shape != self.get_ne())
+        {
+            return Err(Error::InvalidOperands {
+                op: "compare",
+                reason: "the tensors have different shapes",
+            });
+        }
+        let expected = expected.expected_values();
+        if expected.len() != self.nelements() {
+            return Err(Error::LengthMismatch {
+                expected: self.nelements(),
+                found: expected.len(),
+            });
+        }
+
+        let mut comparison = Comparison {
+            mismatches: 0,
+            first_mismatch: None,
+            max_abs_error: 0.0,
+            max_rel_error: 0.0,
+        };
+        let actual = self.expected_values();
+        for (i, (&a, &e)) in actual.iter().zip(&expected).enumerate() {
+            let error = (a - e).abs();
+            let close = error <= atol + rtol * e.abs();
+            if !error.is_nan() {
+                comparison.max_abs_error = comparison.max_abs_error.max(error);
+                if e != 0.0 {
+                    comparison.max_rel_error = comparison.max_rel_error.max(error / e.abs());
+                }
+            }
+            if !close {
+                comparison.mismatches += 1;
+                if comparison.first_mismatch.is_none() {
+                    comparison.first_mismatch = Some(Mismatch {
+                        index: [
+                            i % ne[0],
+                            i / ne[0] % ne[1],
+                            i / (ne[0] * ne[1]) % ne[2],
+                            i / (ne[0] * ne[1] * ne[2]),
+                        ],
+                        actual: a,
+                        expected: e,
+                    });
+                }
+            }
+        }
+        Ok(comparison)
+    }
+}
+
+/// Asserts that a tensor matches the expected values, a tensor or a slice,
+/// within a tolerance as in [`Tensor::allclose`]. The tolerances default to
+/// NumPy's `rtol = 1e-5` and `atol = 1e-8`.
+///
+/// On failure the message names the first mismatching index, the values
+/// there and the largest absolute and relative errors.
+///
+/// ```ignore
+/// assert_allclose!(output, [1.0f32, 2.0, 3.0]);
+/// assert_allclose!(output, expected_tensor, rtol = 1e-3, atol = 1e-6);
+/// ```
+#[macro_export]
+macro_rules! assert_allclose {
+    ($actual:expr, $expected:expr $(,)?) => {
+        $crate::assert_allclose!($actual, $expected, rtol = 1e-5, atol = 1e-8)
+    };
+    ($actual:expr, $expected:expr, rtol = $rtol:expr, atol = $atol:expr $(,)?) => {
+        match $actual.compare(&$expected, $rtol, $atol) {
+            Ok(comparison) if comparison.is_close() => {}
+            Ok(comparison) => panic!(
+                "assertion `{}` is close to `{}` failed: {}",
+                stringify!($actual),
+                stringify!($expected),
+                comparison
+            ),
+            Err(error) => panic!(
+                "assertion `{}` is close to `{}` failed: {}",
+                stringify!($actual),
+                stringify!($expected),
+                error
+            ),
+        }
+    };
+}
@@ -0,0 +1,113 @@
+use crate::context::Context;
+use crate::error::Error;
+use crate::graph::ComputationGraph;
+use crate::slice::check_layout;
+use crate::tensor::{Element, OpKind, Tensor};
+
+/// A graph built once and evaluated many times, e.g. once per token.
+///
+/// Inputs and outputs are looked up by the names given to
+/// [`Context::compile`]. Between runs only the input data changes; the nodes
+/// and the work buffer stay where they are in the context, so repeated
+/// evaluations do not allocate.
+pub struct CompiledGraph<'ctx, 'buf> {
+    ctx: &'ctx Context<'buf>,
+    graph: ComputationGraph,
+    inputs: Vec<(String, Tensor)>,
+    outputs: Vec<(String, Tensor)>,
+}
+
+impl<'buf> Context<'buf> {
+    /// Builds a graph computing `outputs` that can be re-run with new data
+    /// written to `inputs`. Inputs must be tensors created directly in this
+    /// context, not results of ops, and are given their names in ggml as
+    /// well.
+    pub fn compile(
+        &self,
+        inputs: &[(&str, &Tensor)],
+        outputs: &[(&str, &Tensor)],
+        n_threads: i32,
+    ) -> Result<CompiledGraph<'_, 'buf>, Error> {
+        let input_tensors: Vec<&Tensor> = inputs.iter().map(|&(_, t)| t).collect();
+        let output_tensors: Vec<&Tensor> = outputs.iter().map(|&(_, t)| t).collect();
+        self.check_operands("compile", &input_tensors)?;
+        self.check_operands("compile", &output_tensors)?;
+        if input_tensors.iter().any(|t| t.op() != OpKind::None) {
+            return Err(Error::InvalidOperands {
+                op: "compile",
+                reason: "inputs must not be the result of an op",
+            });
+        }
+        if input_tensors.iter().any(|t| !t.is_contiguous()) {
+            return Err(Error::NonContiguous);
+        }
+
+        let mut graph = ComputationGraph::new(n_threads);
+        for output in &output_tensors {
+            graph.build_forward_expand(output);
+        }
+
+        for &(name, tensor) in inputs {
+            tensor.set_name(name)?;
+        }
+
+        let named = |tensors: &[(&str, &Tensor)]| {
+            tensors
+                .iter()
+                .map(|&(name, tensor)| (name.to_owned(), tensor.share()))
+                .collect()
+        };
+        Ok(CompiledGraph {
+            ctx: self,
+            graph,
+            inputs: named(inputs),
+            outputs: named(outputs),
+        })
+    }
+}
+
+impl CompiledGraph<'_, '_> {
+    pub fn input(&self, name: &str) -> Option<&Tensor> {
+        find(&self.inputs, name)
+    }
+
+    pub fn output(&self, name: &str) -> Option<&Tensor> {
+        find(&self.outputs, name)
+    }
+
+    pub fn graph(&self) -> &ComputationGraph {
+        &self.graph
+    }
+
+    /// Overwrites the data of input `name`. The slice must match the input's
+    /// element type and hold exactly as many elements.
+    pub fn set_input<T: Element>(&mut self, name: &str, data: &[T]) -> Result<(), Error> {
+        let tensor = find(&self.inputs, name).ok_or_else(|| Error::UnknownTensor {
+            name: name.to_owned(),
+        })?;
+        tensor.copy_from_slice(data)
+    }
+
+    /// Computes the outputs from the current input data.
+    pub fn run(&mut self) {
+        self.ctx.graph_compute(&mut self.graph);
+    }
+
+    /// The data of output `name` as computed by the last [`CompiledGraph::run`].
+    pub fn output_data<T: Element>(&self, name: &str) -> Result<&[T], Error> {
+        let tensor = find(&self.outputs, name).ok_or_else(|| Error::UnknownTensor {
+            name: name.to_owned(),
+        })?;
+        if !tensor.is_contiguous() {
+            return Err(Error::NonContiguous);
+        }
+        check_layout::<T>(tensor, tensor.nelements())?;
+        Ok(tensor
+            .read_data::<T>()
+            .expect("The output's length was checked above"))
+    }
+}
+
+fn find<'a>(tensors: &'a [(String, Tensor)], name: &str) -> Option<&'a Tensor> {
+    tensors.iter().find(|(n, _)| n == name).map(|(_, t)| t)
+}
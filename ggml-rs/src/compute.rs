@@ -0,0 +1,229 @@
+use crate::context::Context;
+use crate::error::Error;
+use crate::graph::ComputationGraph;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{Scope, ScopedJoinHandle};
+use std::time::{Duration, Instant};
+
+/// Environment variable capping the number of threads any computation uses,
+/// e.g. to share a machine between several processes.
+pub const MAX_THREADS_ENV: &str = "GGML_RS_MAX_THREADS";
+
+/// The number of threads computations use unless told otherwise: the
+/// available parallelism, capped by [`MAX_THREADS_ENV`].
+pub fn default_threads() -> usize {
+    let available = std::thread::available_parallelism().map_or(1, |n| n.get());
+    cap_threads(available)
+}
+
+/// Caps `n_threads` by [`MAX_THREADS_ENV`], using at least one thread.
+pub(crate) fn cap_threads(n_threads: usize) -> usize {
+    let cap = std::env::var(MAX_THREADS_ENV)
+        .ok()
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|&cap| cap > 0);
+    n_threads.min(cap.unwrap_or(usize::MAX)).max(1)
+}
+
+/// Per-call settings of [`Context::graph_compute_with`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ComputeOptions {
+    n_threads: Option<usize>,
+}
+
+impl ComputeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Computes with `n_threads` threads instead of [`default_threads`]. The
+    /// count is still capped by [`MAX_THREADS_ENV`].
+    pub fn with_threads(self, n_threads: usize) -> Self {
+        Self {
+            n_threads: Some(n_threads),
+        }
+    }
+
+    /// The thread count a computation with these options uses.
+    pub fn n_threads(&self) -> usize {
+        self.n_threads.map_or_else(default_threads, cap_threads)
+    }
+}
+
+/// Stops a computation between two nodes, either on request or once a
+/// deadline has passed. Clones share the request, so a token can be handed
+/// to e.g. a connection handler that cancels it when the client disconnects.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token that cancels by itself at `deadline`.
+    pub fn with_deadline(deadline: Instant) -> Self {
+        Self {
+            cancelled: Arc::default(),
+            deadline: Some(deadline),
+        }
+    }
+
+    /// A token that cancels by itself once `timeout` has elapsed from now.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self::with_deadline(Instant::now() + timeout)
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+impl Context<'_> {
+    /// Computes `graph` like [`Context::graph_compute`], with the thread
+    /// count taken from `options` instead of the graph. The graph keeps its
+    /// own count for later calls.
+    pub fn graph_compute_with(&self, graph: &mut ComputationGraph, options: &ComputeOptions) {
+        let n_threads = graph.n_threads();
+        graph.set_threads(options.n_threads() as i32);
+        self.graph_compute(graph);
+        graph.set_threads(n_threads);
+    }
+
+    /// Computes `graph` one node at a time, checking `token` before each
+    /// node. Returns [`Error::Cancelled`] if the token is cancelled first.
+    ///
+    /// Nodes are only ever computed whole, so the context and graph stay
+    /// usable after a cancellation; computing the graph again starts over
+    /// from the first node. Checking between nodes costs some speed for
+    /// graphs with many small nodes, see [`Context::graph_compute_profiled`].
+    pub fn graph_compute_cancellable(
+        &self,
+        graph: &mut ComputationGraph,
+        token: &CancellationToken,
+    ) -> Result<(), Error> {
+        graph.assert_alive();
+        let n_threads = graph.n_threads();
+        self.outside_scratch(|| compute_steps(self.ptr.raw, graph, n_threads, token))
+    }
+
+    /// Computes `graph` on a new thread of `scope`, so the calling thread
+    /// stays free to serve other work and can cancel the computation through
+    /// the returned handle.
+    ///
+    /// The graph is computed as by [`Context::graph_compute_cancellable`],
+    /// with `token` also cancelled by [`ComputeHandle::cancel`]. The context
+    /// is borrowed mutably for the whole computation, because ggml contexts
+    /// must not be used from two threads at once.
+    ///
+    /// # Safety
+    /// The borrow only covers the context's own methods: tensor handles do
+    /// not borrow their context (see [`crate::SharedContext`]). Until the
+    /// returned handle has been joined, or the scope has ended, the calling
+    /// thread must not use any tensor of the context, including the graph's
+    /// nodes and the tensors it was built from. That means no building ops
+    /// with them, e.g. `&a + &b` or [`crate::Tensor::matmul`], which
+    /// allocate in the context the computation uses; no reading or writing
+    /// their data; and no using data read before the call, such as slices
+    /// returned by [`crate::Tensor::read_data`]. Tensors of frozen contexts
+    /// the graph reads from can still be read.
+    pub unsafe fn graph_compute_background<'scope, 'env>(
+        &'env mut self,
+        scope: &'scope Scope<'scope, 'env>,
+        graph: &'env mut ComputationGraph,
+        options: ComputeOptions,
+        token: CancellationToken,
+    ) -> ComputeHandle<'scope> {
+        graph.assert_alive();
+        let job = BackgroundJob {
+            ctx: self.ptr.raw,
+            graph,
+            n_threads: options.n_threads() as i32,
+            token: token.clone(),
+        };
+        ComputeHandle {
+            handle: scope.spawn(move || job.run()),
+            token,
+        }
+    }
+}
+
+/// A computation started by [`Context::graph_compute_background`].
+pub struct ComputeHandle<'scope> {
+    handle: ScopedJoinHandle<'scope, Result<(), Error>>,
+    token: CancellationToken,
+}
+
+impl ComputeHandle<'_> {
+    /// Asks the computation to stop before its next node. The context and
+    /// graph stay usable; a later computation starts from the first node.
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Waits for the computation, returning [`Error::Cancelled`] if it was
+    /// cancelled before computing every node. A panic inside a map op is
+    /// resumed here.
+    pub fn join(self) -> Result<(), Error> {
+        match self.handle.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+struct BackgroundJob<'env> {
+    ctx: NonNull<ggml_internal::ggml_context>,
+    graph: &'env mut ComputationGraph,
+    n_threads: i32,
+    token: CancellationToken,
+}
+
+// SAFETY: `graph_compute_background` borrows the graph exclusively and the
+// context mutably for as long as the job runs, so no other thread can
+// compute or allocate through the context itself. Its tensor handles are
+// not covered by the borrow; the caller promises not to use them until the
+// job has finished, which makes the job's thread the only one touching the
+// context and the graph's data.
+unsafe impl Send for BackgroundJob<'_> {}
+
+impl BackgroundJob<'_> {
+    fn run(self) -> Result<(), Error> {
+        compute_steps(self.ctx, self.graph, self.n_threads, &self.token)
+    }
+}
+
+fn compute_steps(
+    ctx: NonNull<ggml_internal::ggml_context>,
+    graph: &mut ComputationGraph,
+    n_threads: i32,
+    token: &CancellationToken,
+) -> Result<(), Error> {
+    let mut step = graph.step_graph(ctx, n_threads);
+    let mut result = Ok(());
+    for i in 0..graph.n_nodes() {
+        if token.is_cancelled() {
+            result = Err(Error::Cancelled);
+            break;
+        }
+        graph.compute_node(ctx, &mut step, i);
+    }
+    crate::ops::resume_map_op_panic();
+    result
+}
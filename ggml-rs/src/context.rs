@@ -1,57 +1,547 @@
+use crate::compute::cap_threads;
+use crate::error::Error;
 use crate::graph::ComputationGraph;
-use crate::tensor::{DataType, Tensor};
+use crate::memory::Recording;
+use crate::ops::RopeMode;
+use crate::tensor::{DataType, Element, Tensor};
 use crate::Dimension;
-use std::{ffi::c_void, ptr::NonNull, sync::Arc};
+use std::{
+    any::Any,
+    cell::RefCell,
+    ffi::{c_void, CString},
+    fmt,
+    marker::PhantomData,
+    ops::Range,
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
 
 /// Acts as a RAII-guard over a `ggml_internal::ggml_context`, allocating via
 /// ggml_init and dropping via ggml_free
-pub struct Context {
+///
+/// `'buf` is the lifetime of a borrowed arena, see [`ContextBuilder::buffer`];
+/// it is `'static` for contexts that allocate or own their arena. Tensors do
+/// not carry it, so a context with a shorter `'buf` is only handed out by
+/// [`ContextBuilder::build_scoped`], which drops it before the borrow ends
+/// even if the caller would rather leak it.
+///
+/// A context stays on the thread that created it; [`Context::freeze`] turns
+/// one into a [`crate::SharedContext`] that threads can share. It is not
+/// `Send` because its tensors hold handles to it that could stay behind on
+/// the original thread and keep using it while it is used elsewhere.
+pub struct Context<'buf> {
     /// An `Arc` is used to model the relation between the context and the
     /// allocated tensors. Tensors are owned by the object, so a [`GgmlTensor`]
     /// contains a `Weak` reference underneath and doesn't let you do anything
     /// with it if the underlying context has been deallocated.
-    ptr: Arc<NonNull<ggml_internal::ggml_context>>,
+    pub(crate) ptr: Arc<RawContext>,
+    /// Set on the context handed out by [`Context::with_scratch`], which
+    /// shares the ggml context of its parent instead of owning it.
+    pub(crate) scratch: Option<ggml_internal::ggml_scratch>,
+    /// Invariant in `'buf`, so that a context cannot be passed off as one
+    /// borrowing for a shorter time, e.g. to [`Context::attach_borrowed`].
+    _borrowed: PhantomData<fn(&'buf ()) -> &'buf ()>,
 }
-impl Context {
-    pub fn init(mem_size: usize) -> Self {
-        let raw = unsafe {
-            ggml_internal::ggml_init(ggml_internal::ggml_init_params {
-                mem_size,
-                mem_buffer: std::ptr::null_mut(),
-            })
-        };
+
+/// The ggml context behind a [`Context`] and the memory it points into.
+///
+/// The context is freed when the last strong reference goes away rather than
+/// when the [`Context`] is dropped, so that a tensor method that upgraded its
+/// handle on another thread (see [`crate::SharedContext`]) never sees it
+/// freed halfway through.
+pub(crate) struct RawContext {
+    pub(crate) raw: NonNull<ggml_internal::ggml_context>,
+    /// Whether this handle frees the context. Scratch scopes share the ggml
+    /// context of their parent instead.
+    owner: bool,
+    /// Set by [`Context::freeze`]; the context is read-only from then on.
+    frozen: AtomicBool,
+    /// Buffers handed over by [`Context::attach_data`].
+    attached: Mutex<Vec<Box<dyn Any + Send>>>,
+    /// Views built in this context of tensors of frozen ones, by address.
+    /// They share the frozen data and are read-only like it.
+    read_only: Mutex<Vec<usize>>,
+    /// The frozen contexts those views point into, kept alive for as long
+    /// as the views can be read.
+    viewed: Mutex<Vec<Arc<RawContext>>>,
+    /// Every tensor created in the context, kept only for the dry run of
+    /// [`Context::plan`]. Kept here rather than in the [`Context`] so that
+    /// nodes built with operators such as `&a + &b` are recorded too.
+    pub(crate) recorded: Mutex<Option<Recording>>,
+    /// The arena, if it was handed over by [`ContextBuilder::owned_buffer`].
+    /// Dropped after `Drop::drop` has freed the ggml context.
+    arena: Option<Box<[u8]>>,
+}
+
+impl RawContext {
+    fn new(raw: NonNull<ggml_internal::ggml_context>, owner: bool) -> Self {
+        Self {
+            raw,
+            owner,
+            frozen: AtomicBool::new(false),
+            attached: Mutex::new(Vec::new()),
+            read_only: Mutex::new(Vec::new()),
+            viewed: Mutex::new(Vec::new()),
+            recorded: Mutex::new(None),
+            arena: None,
+        }
+    }
+
+    pub(crate) fn as_ptr(&self) -> *mut ggml_internal::ggml_context {
+        self.raw.as_ptr()
+    }
+
+    pub(crate) fn is_frozen(&self) -> bool {
+        self.frozen.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn freeze(&self) {
+        self.frozen.store(true, Ordering::Release);
+    }
+
+    /// Fails if the dry run of [`Context::plan`] has already created as many
+    /// tensors as it was sized for, before ggml runs out of arena and aborts.
+    pub(crate) fn check_room(&self) -> Result<(), Error> {
+        self.recorded
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(Ok(()), Recording::check_room)
+    }
+
+    /// Like `check_room`, for the constructors and operators that cannot
+    /// return an error.
+    pub(crate) fn assert_room(&self) {
+        if let Err(e) = self.check_room() {
+            panic!("{}", e);
+        }
+    }
+
+    /// Adds a tensor just created in the context to the dry run, if any.
+    pub(crate) fn record(&self, tensor: NonNull<ggml_internal::ggml_tensor>) {
+        if let Some(recorded) = self.recorded.lock().unwrap().as_mut() {
+            recorded.tensors.push(tensor);
+        }
+    }
+
+    /// Whether `tensor`, a tensor of this context, must not be written to:
+    /// the context is frozen, or the tensor is a view of frozen data.
+    pub(crate) fn is_read_only(&self, tensor: NonNull<ggml_internal::ggml_tensor>) -> bool {
+        self.is_frozen()
+            || self
+                .read_only
+                .lock()
+                .unwrap()
+                .contains(&(tensor.as_ptr() as usize))
+    }
+}
+
+impl fmt::Debug for RawContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawContext")
+            .field("raw", &self.raw)
+            .field("frozen", &self.is_frozen())
+            .finish()
+    }
+}
+
+impl Drop for RawContext {
+    fn drop(&mut self) {
+        if !self.owner {
+            return;
+        }
+        // SAFETY: Tensors only use the context through an upgraded handle, so
+        // none can be using it once the last one is gone.
+        unsafe {
+            ggml_internal::ggml_free(self.raw.as_ptr());
+        }
+    }
+}
+
+/// The memory a live context's tensors are found in, see [`owner_of`].
+struct Registration {
+    ctx: Weak<RawContext>,
+    /// The arena, which holds the tensor objects of the context.
+    arena: Range<usize>,
+    /// The buffer of a [`Context::with_scratch`] scope, which holds the data
+    /// of the tensors created in the scope.
+    scratch: Option<Range<usize>>,
+}
+
+// SAFETY: The registry only compares addresses and clones handles. Nothing
+// upgrades a handle through it, so a thread that looks a context up never
+// uses or frees it unless it could already reach one of its tensors.
+unsafe impl Send for Registration {}
+
+/// Every context created by this crate, so that tensors ggml hands back, such
+/// as graph nodes and operands, can be tagged with the context they belong to.
+static REGISTRY: Mutex<Vec<Registration>> = Mutex::new(Vec::new());
+
+fn register(ctx: &Arc<RawContext>, scratch: Option<Range<usize>>) {
+    let start = unsafe { ggml_internal::ggml_get_mem_buffer(ctx.as_ptr()) } as usize;
+    let size = unsafe { ggml_internal::ggml_get_mem_size(ctx.as_ptr()) };
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    registry.retain(|r| r.ctx.strong_count() > 0);
+    registry.push(Registration {
+        ctx: Arc::downgrade(ctx),
+        arena: start..start + size,
+        scratch,
+    });
+}
+
+/// The handle of the context `raw` belongs to: the live scratch scope whose
+/// buffer holds its data, or else the context whose arena holds the tensor
+/// object. `None` if that context is gone.
+///
+/// `raw` must point to a tensor whose context is still alive, e.g. an
+/// operand of a live tensor.
+pub(crate) fn owner_of(raw: NonNull<ggml_internal::ggml_tensor>) -> Option<Weak<RawContext>> {
+    let object = raw.as_ptr() as usize;
+    let data = unsafe { (*raw.as_ptr()).data } as usize;
+    let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    let live = || registry.iter().filter(|r| r.ctx.strong_count() > 0);
+    live()
+        .find(|r| r.scratch.as_ref().is_some_and(|s| s.contains(&data)))
+        .or_else(|| live().find(|r| r.scratch.is_none() && r.arena.contains(&object)))
+        .map(|r| Weak::clone(&r.ctx))
+}
+
+/// Where a [`ContextBuilder`] puts the arena.
+#[derive(Debug)]
+enum Arena<'buf> {
+    /// ggml allocates the arena itself.
+    Allocated(usize),
+    Borrowed(&'buf mut [u8]),
+    Owned(Box<[u8]>),
+}
+
+/// Settings for creating a [`Context`] beyond the arena size.
+#[derive(Debug)]
+pub struct ContextBuilder<'buf> {
+    arena: Arena<'buf>,
+    no_alloc: bool,
+}
+
+impl ContextBuilder<'static> {
+    /// A context with an arena of `mem_size` bytes, allocated by ggml.
+    pub fn new(mem_size: usize) -> Self {
         Self {
-            ptr: Arc::new(NonNull::new(raw).expect("Should not be null")),
+            arena: Arena::Allocated(mem_size),
+            no_alloc: false,
         }
     }
 
-    pub fn init_managed(mem: &mut [u8]) -> Self {
+    /// Creates the context. A borrowed arena has to start at a multiple of
+    /// ggml's alignment ([`ARENA_ALIGN`] bytes), otherwise
+    /// [`Error::Misaligned`] is returned.
+    ///
+    /// Only an arena that lives for the whole program can be borrowed this
+    /// way; see [`ContextBuilder::build_scoped`] for shorter ones.
+    pub fn build(self) -> Result<Context<'static>, Error> {
+        self.build_unscoped()
+    }
+}
+
+impl<'buf> ContextBuilder<'buf> {
+    /// Uses `buffer` as the arena instead of letting ggml allocate one. The
+    /// context borrows the buffer for as long as it lives; unless the buffer
+    /// is `'static`, build the context with [`ContextBuilder::build_scoped`].
+    pub fn buffer<'b>(self, buffer: &'b mut [u8]) -> ContextBuilder<'b> {
+        ContextBuilder {
+            arena: Arena::Borrowed(buffer),
+            no_alloc: self.no_alloc,
+        }
+    }
+
+    /// Uses `buffer` as the arena, moving it into the context. If `buffer`
+    /// does not start at a multiple of [`ARENA_ALIGN`] bytes, the bytes
+    /// before the first aligned address are left unused.
+    pub fn owned_buffer(self, buffer: impl Into<Box<[u8]>>) -> Self {
+        Self {
+            arena: Arena::Owned(buffer.into()),
+            ..self
+        }
+    }
+
+    /// With `no_alloc`, tensors are created with their shape and type only;
+    /// the arena holds just their bookkeeping, as measured by
+    /// [`Context::plan`]. Data is attached afterwards with
+    /// [`Context::attach_data`] or [`Tensor::set_data_from`], e.g. pointing
+    /// into a memory-mapped model file.
+    ///
+    /// Until then the tensors have no data: reading, writing or computing
+    /// them is not allowed. Ops on them produce results without data too.
+    pub fn no_alloc(self, no_alloc: bool) -> Self {
+        Self { no_alloc, ..self }
+    }
+
+    /// Creates the context and runs `f` with it, dropping the context
+    /// before returning. Tensors of the context stop working then, like
+    /// those of any dropped context, so none of them can write into the
+    /// borrowed arena, or into buffers given to [`Context::attach_borrowed`],
+    /// after the borrow ends. The arena has to be aligned as for
+    /// [`ContextBuilder::build`].
+    ///
+    /// Buffers attached to the context have to outlive the borrow of the
+    /// arena:
+    ///
+    /// ```compile_fail
+    /// use ggml_rs::{ContextBuilder, DataType};
+    ///
+    /// #[repr(align(16))]
+    /// struct Arena([u8; 4096]);
+    ///
+    /// let mut arena = Arena([0; 4096]);
+    /// ContextBuilder::new(0)
+    ///     .buffer(&mut arena.0)
+    ///     .build_scoped(|ctx| {
+    ///         let tensor = ctx.new_tensor_1d(DataType::F32, 4);
+    ///         let mut data = [0u8; 16];
+    ///         ctx.attach_borrowed(&tensor, &mut data).unwrap();
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn build_scoped<R>(self, f: impl FnOnce(&mut Context<'buf>) -> R) -> Result<R, Error> {
+        let mut ctx = self.build_unscoped()?;
+        Ok(f(&mut ctx))
+    }
+
+    fn build_unscoped(self) -> Result<Context<'buf>, Error> {
+        let (mem_size, mem_buffer, owned) = match self.arena {
+            Arena::Allocated(mem_size) => (mem_size, std::ptr::null_mut(), None),
+            Arena::Borrowed(buffer) => (buffer.len(), buffer.as_mut_ptr(), None),
+            Arena::Owned(mut buffer) => {
+                let skip = buffer.as_ptr().align_offset(ARENA_ALIGN).min(buffer.len());
+                let arena = buffer[skip..].as_mut_ptr();
+                (buffer.len() - skip, arena, Some(buffer))
+            }
+        };
+        if mem_buffer.align_offset(ARENA_ALIGN) != 0 {
+            return Err(Error::Misaligned {
+                required: ARENA_ALIGN,
+            });
+        }
+
         let raw = unsafe {
             ggml_internal::ggml_init(ggml_internal::ggml_init_params {
-                mem_size: mem.len() * std::mem::size_of::<u8>(),
-                mem_buffer: mem.as_mut_ptr() as *mut c_void,
+                mem_size,
+                mem_buffer: mem_buffer as *mut c_void,
+                no_alloc: self.no_alloc,
             })
         };
+        let mut raw = RawContext::new(NonNull::new(raw).expect("Should not be null"), true);
+        raw.arena = owned;
+        Ok(Context::from_handle(raw))
+    }
+}
+
+/// Alignment ggml requires of an arena handed to it.
+pub const ARENA_ALIGN: usize = ggml_internal::GGML_MEM_ALIGN as usize;
+
+impl Context<'static> {
+    pub fn init(mem_size: usize) -> Self {
+        ContextBuilder::new(mem_size)
+            .build()
+            .expect("ggml allocates an aligned arena")
+    }
+}
+
+impl<'buf> Context<'buf> {
+    /// Creates a context using `mem` as its arena.
+    ///
+    /// # Panics
+    /// If `mem` is not aligned to [`ARENA_ALIGN`] bytes; use
+    /// [`ContextBuilder::buffer`] to handle that as an error.
+    ///
+    /// # Safety
+    /// The context must be dropped before the borrow of `mem` ends. Leaking
+    /// it, e.g. with [`std::mem::forget`], keeps its tensors usable while
+    /// they point into memory that is no longer borrowed.
+    #[deprecated(
+        note = "use `ContextBuilder::buffer` with `ContextBuilder::build_scoped`, which \
+                drops the context in time and reports a misaligned buffer as an error"
+    )]
+    pub unsafe fn init_managed(mem: &'buf mut [u8]) -> Self {
+        ContextBuilder::new(0)
+            .buffer(mem)
+            .build_unscoped()
+            .expect("Managed memory must be aligned to ARENA_ALIGN bytes")
+    }
+
+    /// Makes `tensor` use `data` as its element data, like
+    /// [`Context::attach_data`] but without moving the buffer: it stays
+    /// borrowed for as long as the context lives, which is as long as the
+    /// tensor can be used.
+    ///
+    /// `data` has to outlive the context's own borrow:
+    ///
+    /// ```compile_fail
+    /// use ggml_rs::{Context, DataType};
+    ///
+    /// let ctx = Context::init(1 << 20);
+    /// let tensor = ctx.new_tensor_1d(DataType::F32, 4);
+    /// {
+    ///     let mut data = [0u8; 16];
+    ///     ctx.attach_borrowed(&tensor, &mut data).unwrap();
+    /// }
+    /// tensor.set_f32(1.0);
+    /// ```
+    pub fn attach_borrowed(&self, tensor: &Tensor, data: &'buf mut [u8]) -> Result<(), Error> {
+        self.check_operands("attach_borrowed", &[tensor])?;
+        // SAFETY: `data` outlives the context and with it every use of the
+        // tensor.
+        unsafe { tensor.set_data_from(data) }
+    }
+}
+
+impl Context<'_> {
+    /// Takes ownership of a context ggml created itself, e.g. while
+    /// importing a graph.
+    pub(crate) fn from_raw(raw: NonNull<ggml_internal::ggml_context>) -> Self {
+        Self::from_handle(RawContext::new(raw, true))
+    }
+
+    fn from_handle(raw: RawContext) -> Self {
+        let ptr = Arc::new(raw);
+        register(&ptr, None);
         Self {
-            ptr: Arc::new(NonNull::new(raw).expect("Should not be null")),
+            ptr,
+            scratch: None,
+            _borrowed: PhantomData,
+        }
+    }
+
+    /// Makes `tensor` use `data` as its element data, which the context then
+    /// keeps alive for as long as it exists. This is how tensors of a
+    /// [`ContextBuilder::no_alloc`] context get their data, e.g. from a
+    /// buffer pool; replacing the data of a tensor that already has some in
+    /// the arena works too, but the arena space is not reclaimed.
+    ///
+    /// `data` must hold elements of the tensor's type, at least
+    /// [`Tensor::nelements`] of them.
+    pub fn attach_data<T: Element>(
+        &self,
+        tensor: &Tensor,
+        data: impl Into<Box<[T]>>,
+    ) -> Result<(), Error> {
+        self.check_operands("attach_data", &[tensor])?;
+        let dtype = tensor.get_type();
+        if dtype != T::DATA_TYPE {
+            return Err(Error::TypeMismatch {
+                expected: dtype,
+                found: T::DATA_TYPE,
+            });
+        }
+        let mut data = data.into();
+        // SAFETY: Every `Element` is plain data, so its bytes can be viewed
+        // as such. The buffer is moved into the context below, and moving a
+        // box does not move its heap allocation.
+        unsafe {
+            let bytes = std::slice::from_raw_parts_mut(
+                data.as_mut_ptr() as *mut u8,
+                std::mem::size_of_val(&*data),
+            );
+            tensor.set_data_from(bytes)?;
+        }
+        self.ptr.attached.lock().unwrap().push(Box::new(data));
+        Ok(())
+    }
+
+    /// Verifies that every operand of `op` was created in this context or in
+    /// a frozen one, so a graph never references memory owned by another
+    /// arena that could change or be freed under it.
+    pub(crate) fn check_operands(
+        &self,
+        op: &'static str,
+        operands: &[&Tensor],
+    ) -> Result<(), Error> {
+        self.check_room()?;
+        // Tensors of a scratch scope hold their own handle to the shared
+        // ggml context, so compare the ggml contexts themselves.
+        for (operand, t) in operands.iter().enumerate() {
+            let ctx = t.ctx.upgrade().ok_or(Error::ContextDropped)?;
+            if ctx.raw == self.ptr.raw {
+                continue;
+            }
+            if !ctx.is_frozen() {
+                return Err(Error::ContextMismatch { op, operand });
+            }
+            // ggml writes to every node and parameter of a graph while
+            // computing it, which would race between the threads sharing the
+            // frozen context, so only plain data tensors can be used.
+            if !t.is_plain_leaf() {
+                return Err(Error::InvalidOperands {
+                    op,
+                    reason: "only leaf tensors of a frozen context can be used by other contexts",
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Wraps the result of a view op on `source`, which shares its data. A
+    /// view of a frozen tensor, or of a view of one, is read-only as well,
+    /// and keeps the frozen context alive since its data lives there.
+    fn new_view(
+        &self,
+        raw: *mut ggml_internal::ggml_tensor,
+        source: &Tensor,
+        dim: Dimension,
+        shape: [usize; 4],
+    ) -> Tensor {
+        let view = self.new_tensor_raw(raw, dim, shape);
+        let source_ctx = source
+            .ctx
+            .upgrade()
+            .expect("The operands were checked to be alive");
+        if source_ctx.is_read_only(source.ptr) {
+            let frozen = if source_ctx.is_frozen() {
+                vec![source_ctx]
+            } else {
+                source_ctx.viewed.lock().unwrap().clone()
+            };
+            let mut viewed = self.ptr.viewed.lock().unwrap();
+            for ctx in frozen {
+                if !viewed.iter().any(|v| Arc::ptr_eq(v, &ctx)) {
+                    viewed.push(ctx);
+                }
+            }
+            let mut read_only = self.ptr.read_only.lock().unwrap();
+            read_only.push(view.ptr.as_ptr() as usize);
         }
+        view
     }
 
-    fn new_tensor_raw(
+    pub(crate) fn new_tensor_raw(
         &self,
         raw: *mut ggml_internal::ggml_tensor,
         dim: Dimension,
         shape: [usize; 4],
     ) -> Tensor {
+        let ptr = NonNull::new(raw).expect("Should not be null");
+        self.ptr.record(ptr);
         Tensor {
-            ptr: NonNull::new(raw).expect("Should not be null"),
+            ptr,
             ctx: Arc::downgrade(&self.ptr),
             dim: dim,
             shape: shape,
         }
     }
 
+    /// See [`RawContext::check_room`].
+    pub(crate) fn check_room(&self) -> Result<(), Error> {
+        self.ptr.check_room()
+    }
+
+    fn assert_room(&self) {
+        self.ptr.assert_room()
+    }
+
     pub fn new_tensor_1d(&self, dtype: DataType, ne0: usize) -> Tensor {
+        self.assert_room();
         let raw = unsafe {
             ggml_internal::ggml_new_tensor_1d(self.ptr.as_ptr(), dtype.into(), ne0 as i32)
         };
@@ -59,6 +549,7 @@ impl Context {
     }
 
     pub fn new_tensor_2d(&self, dtype: DataType, ne0: usize, ne1: usize) -> Tensor {
+        self.assert_room();
         let raw = unsafe {
             ggml_internal::ggml_new_tensor_2d(
                 self.ptr.as_ptr(),
@@ -71,6 +562,7 @@ impl Context {
     }
 
     pub fn new_tensor_3d(&self, dtype: DataType, ne0: usize, ne1: usize, ne2: usize) -> Tensor {
+        self.assert_room();
         let raw = unsafe {
             ggml_internal::ggml_new_tensor_3d(
                 self.ptr.as_ptr(),
@@ -84,78 +576,120 @@ impl Context {
     }
 
     pub fn new_f32(&self, x: f32) -> Tensor {
+        self.assert_room();
         let raw = unsafe { ggml_internal::ggml_new_f32(self.ptr.as_ptr(), x) };
         self.new_tensor_raw(raw, Dimension::Scalar, [1, 1, 1, 1])
     }
 
-    pub fn op_get_rows(&self, a: &Tensor, b: &Tensor) -> Tensor {
+    pub fn op_get_rows(&self, a: &Tensor, b: &Tensor) -> Result<Tensor, Error> {
+        self.check_operands("get_rows", &[a, b])?;
         let tensor = unsafe {
             ggml_internal::ggml_get_rows(self.ptr.as_ptr(), a.ptr.as_ptr(), b.ptr.as_ptr())
         };
-        self.new_tensor_raw(tensor, a.dim.clone(), a.shape) // WARNING: wrong.
+        Ok(self.new_tensor_raw(tensor, a.dim.clone(), a.shape)) // WARNING: wrong.
     }
 
-    pub fn op_norm(&self, a: &Tensor) -> Tensor {
+    pub fn op_norm(&self, a: &Tensor) -> Result<Tensor, Error> {
+        self.check_operands("norm", &[a])?;
         let tensor = unsafe { ggml_internal::ggml_norm(self.ptr.as_ptr(), a.ptr.as_ptr()) };
-        self.new_tensor_raw(tensor, a.dim.clone(), a.shape) // WARNING: wrong.
+        Ok(self.new_tensor_raw(tensor, a.dim.clone(), a.shape)) // WARNING: wrong.
     }
 
-    pub fn op_mul(&self, a: &Tensor, b: &Tensor) -> Tensor {
+    pub fn op_mul(&self, a: &Tensor, b: &Tensor) -> Result<Tensor, Error> {
+        self.check_operands("mul", &[a, b])?;
         let tensor =
             unsafe { ggml_internal::ggml_mul(self.ptr.as_ptr(), a.ptr.as_ptr(), b.ptr.as_ptr()) };
-        self.new_tensor_raw(tensor, a.dim.clone(), a.shape) // WARNING: wrong.
+        Ok(self.new_tensor_raw(tensor, a.dim.clone(), a.shape)) // WARNING: wrong.
     }
 
-    pub fn op_repeat(&self, a: &Tensor, b: &Tensor) -> Tensor {
+    pub fn op_repeat(&self, a: &Tensor, b: &Tensor) -> Result<Tensor, Error> {
+        self.check_operands("repeat", &[a, b])?;
         let tensor = unsafe {
             ggml_internal::ggml_repeat(self.ptr.as_ptr(), a.ptr.as_ptr(), b.ptr.as_ptr())
         };
-        self.new_tensor_raw(tensor, a.dim.clone(), a.shape) // WARNING: wrong.
+        Ok(self.new_tensor_raw(tensor, a.dim.clone(), a.shape)) // WARNING: wrong.
+    }
+
+    pub fn op_sqr(&self, a: &Tensor) -> Result<Tensor, Error> {
+        self.check_operands("sqr", &[a])?;
+        let tensor = unsafe { ggml_internal::ggml_sqr(self.ptr.as_ptr(), a.ptr.as_ptr()) };
+        Ok(self.new_tensor_raw(tensor, a.dim.clone(), a.shape))
+    }
+
+    /// Sums all elements of `a` into a single-element tensor.
+    pub fn op_sum(&self, a: &Tensor) -> Result<Tensor, Error> {
+        self.check_operands("sum", &[a])?;
+        let tensor = unsafe { ggml_internal::ggml_sum(self.ptr.as_ptr(), a.ptr.as_ptr()) };
+        Ok(self.new_tensor_raw(tensor, Dimension::Scalar, [1, 1, 1, 1]))
     }
 
-    pub fn op_mul_mat(&self, a: &Tensor, b: &Tensor) -> Tensor {
+    pub fn op_mul_mat(&self, a: &Tensor, b: &Tensor) -> Result<Tensor, Error> {
+        self.check_operands("mul_mat", &[a, b])?;
         let tensor = unsafe {
             ggml_internal::ggml_mul_mat(self.ptr.as_ptr(), a.ptr.as_ptr(), b.ptr.as_ptr())
         };
-        self.new_tensor_raw(tensor, a.dim.clone(), a.shape) // WARNING: wrong.
+        Ok(self.new_tensor_raw(tensor, a.dim.clone(), a.shape)) // WARNING: wrong.
     }
 
-    pub fn op_add(&self, a: &Tensor, b: &Tensor) -> Tensor {
+    pub fn op_add(&self, a: &Tensor, b: &Tensor) -> Result<Tensor, Error> {
+        self.check_operands("add", &[a, b])?;
         let tensor =
             unsafe { ggml_internal::ggml_add(self.ptr.as_ptr(), a.ptr.as_ptr(), b.ptr.as_ptr()) };
-        self.new_tensor_raw(tensor, a.dim.clone(), a.shape) // WARNING: wrong.
+        Ok(self.new_tensor_raw(tensor, a.dim.clone(), a.shape)) // WARNING: wrong.
     }
 
-    pub fn op_scale(&self, a: &Tensor, b: &Tensor) -> Tensor {
+    pub fn op_scale(&self, a: &Tensor, b: &Tensor) -> Result<Tensor, Error> {
+        self.check_operands("scale", &[a, b])?;
         let tensor =
             unsafe { ggml_internal::ggml_scale(self.ptr.as_ptr(), a.ptr.as_ptr(), b.ptr.as_ptr()) };
-        self.new_tensor_raw(tensor, a.dim.clone(), a.shape) // WARNING: wrong.
+        Ok(self.new_tensor_raw(tensor, a.dim.clone(), a.shape)) // WARNING: wrong.
     }
 
-    pub fn op_diag_mask_inf(&self, a: &Tensor, n_past: i32) -> Tensor {
+    pub fn op_diag_mask_inf(&self, a: &Tensor, n_past: i32) -> Result<Tensor, Error> {
+        self.check_operands("diag_mask_inf", &[a])?;
         let tensor =
             unsafe { ggml_internal::ggml_diag_mask_inf(self.ptr.as_ptr(), a.ptr.as_ptr(), n_past) };
-        self.new_tensor_raw(tensor, a.dim.clone(), a.shape) // WARNING: wrong.
+        Ok(self.new_tensor_raw(tensor, a.dim.clone(), a.shape)) // WARNING: wrong.
     }
 
-    pub fn op_soft_max(&self, a: &Tensor) -> Tensor {
+    pub fn op_soft_max(&self, a: &Tensor) -> Result<Tensor, Error> {
+        self.check_operands("soft_max", &[a])?;
         let tensor = unsafe { ggml_internal::ggml_soft_max(self.ptr.as_ptr(), a.ptr.as_ptr()) };
-        self.new_tensor_raw(tensor, a.dim.clone(), a.shape) // WARNING: wrong.
+        Ok(self.new_tensor_raw(tensor, a.dim.clone(), a.shape)) // WARNING: wrong.
     }
 
-    pub fn op_view_1d(&self, a: &Tensor, ne0: i32, offset: usize) -> Tensor {
+    pub fn op_view_1d(&self, a: &Tensor, ne0: i32, offset: usize) -> Result<Tensor, Error> {
+        self.check_operands("view_1d", &[a])?;
         let tensor =
             unsafe { ggml_internal::ggml_view_1d(self.ptr.as_ptr(), a.ptr.as_ptr(), ne0, offset) };
-        self.new_tensor_raw(tensor, a.dim.clone(), a.shape) // WARNING: wrong.
+        Ok(self.new_view(tensor, a, a.dim.clone(), a.shape)) // WARNING: wrong.
     }
 
-    pub fn op_cpy(&self, a: &Tensor, b: &Tensor) -> Tensor {
+    /// Copies `a` into `b` when the graph is computed. `b` cannot be a
+    /// tensor of a frozen context, nor a view of one.
+    pub fn op_cpy(&self, a: &Tensor, b: &Tensor) -> Result<Tensor, Error> {
+        self.check_operands("cpy", &[a, b])?;
+        let b_ctx = b.ctx.upgrade().ok_or(Error::ContextDropped)?;
+        if b_ctx.is_read_only(b.ptr) {
+            return Err(Error::InvalidOperands {
+                op: "cpy",
+                reason: "cannot copy into a tensor of a frozen context",
+            });
+        }
         let tensor =
             unsafe { ggml_internal::ggml_cpy(self.ptr.as_ptr(), a.ptr.as_ptr(), b.ptr.as_ptr()) };
-        self.new_tensor_raw(tensor, a.dim.clone(), a.shape) // WARNING: wrong.
+        Ok(self.new_tensor_raw(tensor, a.dim.clone(), a.shape)) // WARNING: wrong.
     }
 
-    pub fn op_permute(&self, a: &Tensor, axis0: i32, axis1: i32, axis2: i32, axis3: i32) -> Tensor {
+    pub fn op_permute(
+        &self,
+        a: &Tensor,
+        axis0: i32,
+        axis1: i32,
+        axis2: i32,
+        axis3: i32,
+    ) -> Result<Tensor, Error> {
+        self.check_operands("permute", &[a])?;
         let tensor = unsafe {
             ggml_internal::ggml_permute(
                 self.ptr.as_ptr(),
@@ -166,39 +700,174 @@ impl Context {
                 axis3,
             )
         };
-        self.new_tensor_raw(tensor, a.dim.clone(), a.shape) // WARNING: wrong.
+        Ok(self.new_view(tensor, a, a.dim.clone(), a.shape)) // WARNING: wrong.
     }
-    pub fn op_reshape_3d(&self, a: &Tensor, ne0: i32, ne1: i32, ne2: i32) -> Tensor {
+    pub fn op_reshape_3d(&self, a: &Tensor, ne0: i32, ne1: i32, ne2: i32) -> Result<Tensor, Error> {
+        self.check_operands("reshape_3d", &[a])?;
         let tensor = unsafe {
             ggml_internal::ggml_reshape_3d(self.ptr.as_ptr(), a.ptr.as_ptr(), ne0, ne1, ne2)
         };
-        self.new_tensor_raw(tensor, a.dim.clone(), a.shape) // WARNING: wrong.
+        Ok(self.new_view(tensor, a, a.dim.clone(), a.shape)) // WARNING: wrong.
     }
 
-    pub fn op_rope(&self, a: &Tensor, npast: i32, ndims: i32, mode: i32) -> Tensor {
+    /// Rotary position embedding. [`RopeMode::Glm`] also needs the context
+    /// length and is rejected here, see [`Context::op_rope_with_n_ctx`].
+    pub fn op_rope(
+        &self,
+        a: &Tensor,
+        npast: i32,
+        ndims: i32,
+        mode: RopeMode,
+    ) -> Result<Tensor, Error> {
+        self.check_operands("rope", &[a])?;
+        if mode == RopeMode::Glm {
+            return Err(Error::InvalidOperands {
+                op: "rope",
+                reason: "GLM mode needs the context length, use `op_rope_with_n_ctx`",
+            });
+        }
         let tensor = unsafe {
-            ggml_internal::ggml_rope(self.ptr.as_ptr(), a.ptr.as_ptr(), npast, ndims, mode)
+            ggml_internal::ggml_rope(
+                self.ptr.as_ptr(),
+                a.ptr.as_ptr(),
+                npast,
+                ndims,
+                mode.into(),
+                0,
+            )
         };
-        self.new_tensor_raw(tensor, a.dim.clone(), a.shape) // WARNING: wrong.
+        Ok(self.new_tensor_raw(tensor, a.dim.clone(), a.shape)) // WARNING: wrong.
     }
 
+    /// Computes every node of `graph` with the graph's thread count, capped
+    /// by [`crate::MAX_THREADS_ENV`].
+    ///
+    /// Unlike [`Context::graph_compute_with`], this does not fall back to
+    /// [`crate::default_threads`]: the graph's count was chosen when it was
+    /// built. Compute with `&ComputeOptions::new()` to use the available
+    /// parallelism instead.
     pub fn graph_compute(&self, graph: &mut ComputationGraph) {
-        unsafe {
+        graph.assert_alive();
+        graph.set_threads(cap_threads(graph.n_threads() as usize) as i32);
+        self.outside_scratch(|| unsafe {
             ggml_internal::ggml_graph_compute(self.ptr.as_ptr(), &mut graph.inner);
+        });
+        graph.record_work();
+        crate::ops::resume_map_op_panic();
+    }
+
+    /// Runs `build` with a context whose new tensors keep their data in
+    /// `buffer` instead of the arena; only their bookkeeping goes to the
+    /// arena. Intermediate activations of one layer can be built and
+    /// computed this way, with the buffer reused for every layer, so that
+    /// memory does not grow with depth.
+    ///
+    /// Tensors created inside `build`, including ones built with operators
+    /// such as `&a + &b` from tensors of `self`, are only usable until it
+    /// returns: using one afterwards panics like using a tensor of a dropped
+    /// context, and so does computing a graph built from them. Tensors of
+    /// `self` can be used as operands inside `build`, but `self` is borrowed
+    /// for the duration so that nothing else allocates while the scratch is
+    /// active.
+    pub fn with_scratch<R>(
+        &mut self,
+        buffer: &mut [u8],
+        build: impl FnOnce(&Context<'_>) -> R,
+    ) -> R {
+        let scratch = ggml_internal::ggml_scratch {
+            offs: 0,
+            size: buffer.len(),
+            data: buffer.as_mut_ptr() as *mut c_void,
+        };
+        let ptr = Arc::new(RawContext::new(self.ptr.raw, false));
+        let start = buffer.as_ptr() as usize;
+        register(&ptr, Some(start..start + buffer.len()));
+        let scope = Context {
+            ptr,
+            scratch: Some(scratch),
+            _borrowed: PhantomData,
+        };
+
+        unsafe { ggml_internal::ggml_set_scratch(self.ptr.as_ptr(), scratch) };
+        ACTIVE_SCRATCH.with(|scopes| scopes.borrow_mut().push(Arc::downgrade(&scope.ptr)));
+        // Switches the scratch off again even if `build` panics.
+        let _guard = ScratchGuard(self.ptr.raw);
+        build(&scope)
+    }
+
+    /// Runs `f` with the scratch buffer of a [`Context::with_scratch`] scope
+    /// switched off. ggml allocates a graph's work buffer in the context the
+    /// first time the graph is computed, and it must not end up in a scratch
+    /// buffer that the graph can outlive.
+    pub(crate) fn outside_scratch<R>(&self, f: impl FnOnce() -> R) -> R {
+        match self.scratch {
+            Some(scratch) => {
+                let offs =
+                    unsafe { ggml_internal::ggml_set_scratch(self.ptr.as_ptr(), NO_SCRATCH) };
+                let result = f();
+                unsafe {
+                    ggml_internal::ggml_set_scratch(
+                        self.ptr.as_ptr(),
+                        ggml_internal::ggml_scratch { offs, ..scratch },
+                    )
+                };
+                result
+            }
+            None => f(),
         }
     }
 
+    /// Looks up a tensor of the context by the name given to it with
+    /// [`Tensor::set_name`], e.g. a weight named by
+    /// [`crate::io::TensorNames`]. Names longer than [`Tensor::MAX_NAME_LEN`]
+    /// are shortened as by [`crate::io::tensor_name`]. If several tensors
+    /// share the name, the first one created is returned.
+    pub fn get_tensor(&self, name: &str) -> Option<Tensor> {
+        let name = CString::new(crate::io::tensor_name(name).as_ref()).ok()?;
+        let raw = unsafe { ggml_internal::ggml_get_tensor(self.ptr.as_ptr(), name.as_ptr()) };
+        (!raw.is_null()).then(|| Tensor::from_raw(raw, Arc::downgrade(&self.ptr)))
+    }
+
     pub fn used_mem(&self) -> usize {
         unsafe { ggml_internal::ggml_used_mem(self.ptr.as_ptr()) }
     }
 }
 
-impl Drop for Context {
+thread_local! {
+    /// Liveness handles of the [`Context::with_scratch`] scopes running on
+    /// this thread, innermost last.
+    static ACTIVE_SCRATCH: RefCell<Vec<Weak<RawContext>>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+struct ScratchGuard(NonNull<ggml_internal::ggml_context>);
+
+impl Drop for ScratchGuard {
     fn drop(&mut self) {
-        // SAFETY: The only non-weak copy of ptr is no longer accessible after
-        // this drop call.
-        unsafe {
-            ggml_internal::ggml_free(self.ptr.as_ptr());
-        }
+        unsafe { ggml_internal::ggml_set_scratch(self.0.as_ptr(), NO_SCRATCH) };
+        ACTIVE_SCRATCH.with(|scopes| scopes.borrow_mut().pop());
     }
 }
+
+/// The handle to give a tensor that is about to be created in `ctx` without
+/// going through a [`Context`], e.g. by `&a + &b`. While a scratch scope of
+/// the same ggml context is active, ggml puts the new tensor's data into the
+/// scratch buffer, so the tensor must not outlive the scope.
+pub(crate) fn allocation_handle(ctx: &Arc<RawContext>) -> Weak<RawContext> {
+    ACTIVE_SCRATCH
+        .with(|scopes| {
+            scopes
+                .borrow()
+                .iter()
+                .rev()
+                .find(|scope| scope.upgrade().is_some_and(|scope| scope.raw == ctx.raw))
+                .cloned()
+        })
+        .unwrap_or_else(|| Arc::downgrade(ctx))
+}
+
+const NO_SCRATCH: ggml_internal::ggml_scratch = ggml_internal::ggml_scratch {
+    offs: 0,
+    size: 0,
+    data: std::ptr::null_mut(),
+};
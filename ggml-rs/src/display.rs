@@ -0,0 +1,181 @@
+use crate::tensor::{DataType, Tensor};
+use std::fmt;
+
+/// Summarized, human-readable formatting of a tensor, see
+/// [`Tensor::display`].
+///
+/// Shows the name, type, shape and strides, followed by the values nested
+/// NumPy-style with the outermost axis first. Tensors with more elements
+/// than the threshold only show the first and last few entries along each
+/// axis, so that large weights print quickly.
+#[derive(Clone, Copy, Debug)]
+pub struct TensorDisplay<'a> {
+    tensor: &'a Tensor,
+    precision: usize,
+    edge_items: usize,
+    threshold: usize,
+}
+
+impl Tensor {
+    /// Formats the tensor with the default settings: 4 decimals, and 3 items
+    /// at each end of an axis for tensors of more than 1000 elements.
+    /// `Display` on the tensor itself uses these too, with the precision
+    /// taken from the format string if given, e.g. `{:.2}`.
+    pub fn display(&self) -> TensorDisplay<'_> {
+        TensorDisplay {
+            tensor: self,
+            precision: 4,
+            edge_items: 3,
+            threshold: 1000,
+        }
+    }
+}
+
+impl TensorDisplay<'_> {
+    /// Decimals shown for floating point values.
+    pub fn precision(self, precision: usize) -> Self {
+        Self { precision, ..self }
+    }
+
+    /// Items shown at the start and at the end of a summarized axis.
+    pub fn edge_items(self, edge_items: usize) -> Self {
+        Self { edge_items, ..self }
+    }
+
+    /// Tensors with more elements than this are summarized.
+    pub fn threshold(self, threshold: usize) -> Self {
+        Self { threshold, ..self }
+    }
+
+    /// Indices shown along an axis of `len` elements; `None` marks the gap.
+    fn indices(&self, len: usize, summarize: bool) -> Vec<Option<usize>> {
+        if summarize && len > 2 * self.edge_items {
+            (0..self.edge_items)
+                .map(Some)
+                .chain(std::iter::once(None))
+                .chain((len - self.edge_items..len).map(Some))
+                .collect()
+        } else {
+            (0..len).map(Some).collect()
+        }
+    }
+
+    fn write_value(&self, f: &mut fmt::Formatter<'_>, offset: usize) -> fmt::Result {
+        let data = self.tensor.data() as *const u8;
+        // SAFETY: Callers only pass offsets of elements within the tensor.
+        unsafe {
+            let ptr = data.add(offset);
+            match self.tensor.get_type() {
+                DataType::I8 => write!(f, "{}", *(ptr as *const i8)),
+                DataType::I16 => write!(f, "{}", (ptr as *const i16).read_unaligned()),
+                DataType::I32 => write!(f, "{}", (ptr as *const i32).read_unaligned()),
+                DataType::F16 => {
+                    let value =
+                        ggml_internal::ggml_fp16_to_fp32((ptr as *const u16).read_unaligned());
+                    write!(f, "{:.*}", self.precision, value)
+                }
+                DataType::F32 => {
+                    write!(
+                        f,
+                        "{:.*}",
+                        self.precision,
+                        (ptr as *const f32).read_unaligned()
+                    )
+                }
+                _ => write!(f, "?"),
+            }
+        }
+    }
+
+    /// Writes the values along `axis`, counting down from the outermost one,
+    /// for the sub-tensor starting `offset` bytes into the data.
+    fn write_axis(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        shape: &[usize],
+        strides: &[usize],
+        axis: usize,
+        offset: usize,
+        summarize: bool,
+    ) -> fmt::Result {
+        let depth = shape.len() - 1 - axis;
+        // Quantized values can only be read a whole row at a time.
+        let mut row = Vec::new();
+        if axis == 0 && self.tensor.get_type().is_quantized() {
+            row.resize(shape[0], 0.0);
+            self.tensor.read_row_f32(offset, &mut row);
+        }
+        write!(f, "[")?;
+        for (n, index) in self.indices(shape[axis], summarize).into_iter().enumerate() {
+            if n > 0 {
+                if axis == 0 {
+                    write!(f, ", ")?;
+                } else {
+                    // One blank line per axis below the innermost two, like
+                    // NumPy.
+                    write!(f, ",{}", "\n".repeat(axis))?;
+                    write!(f, "{:width$}", "", width = depth + 1)?;
+                }
+            }
+            match index {
+                None => write!(f, "...")?,
+                Some(i) if !row.is_empty() => write!(f, "{:.*}", self.precision, row[i])?,
+                Some(i) if axis == 0 => self.write_value(f, offset + i * strides[0])?,
+                Some(i) => self.write_axis(
+                    f,
+                    shape,
+                    strides,
+                    axis - 1,
+                    offset + i * strides[axis],
+                    summarize,
+                )?,
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+impl fmt::Display for TensorDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tensor = self.tensor;
+        let n_dims = tensor.n_dims();
+        let shape: Vec<usize> = tensor.get_ne()[..n_dims]
+            .iter()
+            .map(|&n| n as usize)
+            .collect();
+        let strides = &tensor.get_nb()[..n_dims];
+
+        let name = tensor.name();
+        if !name.is_empty() {
+            write!(f, "{:?} ", name)?;
+        }
+        write!(
+            f,
+            "{:?} {:?} strides {:?}",
+            tensor.get_type(),
+            shape,
+            strides
+        )?;
+        if tensor.data().is_null() {
+            return write!(f, " (no data)");
+        }
+        if tensor.get_type() == DataType::COUNT {
+            return Ok(());
+        }
+        writeln!(f)?;
+        let summarize = tensor.nelements() > self.threshold;
+        self.write_axis(f, &shape, strides, n_dims - 1, 0, summarize)
+    }
+}
+
+/// Summarized like [`Tensor::display`]; the precision of the format string
+/// is used if given.
+impl fmt::Display for Tensor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let display = self.display();
+        match f.precision() {
+            Some(precision) => display.precision(precision).fmt(f),
+            None => display.fmt(f),
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use crate::tensor::{DataType, OpKind};
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Errors reported while building or evaluating a graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// An operand of `op` was created in a different context than the one
+    /// building the op. Use [`crate::Tensor::copy_to`] to move data between
+    /// contexts deliberately.
+    ContextMismatch { op: &'static str, operand: usize },
+    /// The context a tensor was created in has already been dropped.
+    ContextDropped,
+    /// The operands of `op` have shapes or types it cannot be applied to, or
+    /// ask for a variant ggml does not implement.
+    InvalidOperands {
+        op: &'static str,
+        reason: &'static str,
+    },
+    /// The tensor's elements are not laid out contiguously in memory.
+    NonContiguous,
+    /// The tensor has no data to read or write, e.g. because it was created
+    /// in a [`crate::ContextBuilder::no_alloc`] context.
+    NoData,
+    /// A graph file could not be written or read back.
+    GraphFile { path: PathBuf, source: IoError },
+    /// No input or output with this name exists.
+    UnknownTensor { name: String },
+    /// Data of one element type was used with a tensor of another.
+    TypeMismatch { expected: DataType, found: DataType },
+    /// Data with the wrong number of elements was used with a tensor.
+    LengthMismatch { expected: usize, found: usize },
+    /// The computation was cancelled before every node was computed.
+    Cancelled,
+    /// A buffer is smaller than the data it has to hold.
+    BufferTooSmall { needed: usize, found: usize },
+    /// The dry run of [`crate::Context::plan`] tried to create more than the
+    /// `max` tensors it was sized for.
+    TooManyTensors { max: usize },
+    /// A buffer does not start at a multiple of `required` bytes.
+    Misaligned { required: usize },
+    /// The tensor belongs to a context frozen by [`crate::Context::freeze`],
+    /// which cannot be modified any more, or is a view of such a tensor.
+    Frozen,
+    /// The name is too long for ggml or contains a nul character, see
+    /// [`crate::Tensor::set_name`].
+    InvalidName { name: String },
+    /// Node `node` of a graph, the first one to do so, holds NaNs or
+    /// infinities, see [`crate::ComputationGraph::check_finite`].
+    NonFinite {
+        node: usize,
+        name: String,
+        op: OpKind,
+        nan_count: usize,
+        inf_count: usize,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ContextMismatch { op, operand } => write!(
+                f,
+                "operand {} of `{}` belongs to a different context",
+                operand, op
+            ),
+            Error::ContextDropped => write!(f, "the tensor's context has been dropped"),
+            Error::InvalidOperands { op, reason } => {
+                write!(f, "invalid operands to `{}`: {}", op, reason)
+            }
+            Error::NonContiguous => write!(f, "the tensor is not contiguous"),
+            Error::NoData => write!(f, "the tensor has no data"),
+            Error::GraphFile { path, source } => write!(
+                f,
+                "could not access graph file `{}`: {}",
+                path.display(),
+                source
+            ),
+            Error::UnknownTensor { name } => write!(f, "no tensor named `{}`", name),
+            Error::TypeMismatch { expected, found } => {
+                write!(f, "expected data of type {:?}, found {:?}", expected, found)
+            }
+            Error::LengthMismatch { expected, found } => {
+                write!(f, "expected {} elements, found {}", expected, found)
+            }
+            Error::Cancelled => write!(f, "the computation was cancelled"),
+            Error::BufferTooSmall { needed, found } => write!(
+                f,
+                "the buffer holds {} bytes but {} are needed",
+                found, needed
+            ),
+            Error::TooManyTensors { max } => write!(
+                f,
+                "the dry run creates more than {} tensors, raise `max_tensors`",
+                max
+            ),
+            Error::Misaligned { required } => {
+                write!(f, "the buffer is not aligned to {} bytes", required)
+            }
+            Error::Frozen => write!(f, "the tensor's context is frozen"),
+            Error::InvalidName { name } => write!(
+                f,
+                "`{}` is not a valid tensor name: names are at most {} bytes without nul characters",
+                name,
+                crate::Tensor::MAX_NAME_LEN
+            ),
+            Error::NonFinite {
+                node,
+                name,
+                op,
+                nan_count,
+                inf_count,
+            } => write!(
+                f,
+                "node {} (`{}`, {}) holds {} NaN and {} infinite values",
+                node,
+                name,
+                op.name(),
+                nan_count,
+                inf_count
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::GraphFile { source, .. } => Some(source.get_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// An [`io::Error`] kept in an [`Error`]. It is shared so that the error can
+/// be cloned, and compares equal to errors of the same kind.
+#[derive(Debug, Clone)]
+pub struct IoError(Arc<io::Error>);
+
+impl IoError {
+    pub fn kind(&self) -> io::ErrorKind {
+        self.0.kind()
+    }
+
+    pub fn get_ref(&self) -> &io::Error {
+        &self.0
+    }
+}
+
+impl From<io::Error> for IoError {
+    fn from(e: io::Error) -> Self {
+        IoError(Arc::new(e))
+    }
+}
+
+impl From<io::ErrorKind> for IoError {
+    fn from(kind: io::ErrorKind) -> Self {
+        io::Error::from(kind).into()
+    }
+}
+
+impl PartialEq for IoError {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind() == other.kind()
+    }
+}
+
+impl Eq for IoError {}
+
+impl fmt::Display for IoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
@@ -1,21 +1,486 @@
+use crate::compute::cap_threads;
+use crate::context::{owner_of, Context, RawContext};
+use crate::error::{Error, IoError};
 use crate::tensor::Tensor;
+use crate::work;
+use std::ffi::{CString, OsString};
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::ptr::NonNull;
+use std::sync::{Arc, Weak};
+
 pub struct ComputationGraph {
     pub(crate) inner: ggml_internal::ggml_cgraph,
+    /// The context the graph is built in, taken from the first tensor the
+    /// graph is expanded with.
+    pub(crate) ctx: Weak<RawContext>,
+    /// The context each node belongs to, used to hand the nodes back out as
+    /// [`Tensor`]s.
+    node_owners: Vec<Weak<RawContext>>,
+    /// The context each leaf belongs to.
+    leaf_owners: Vec<Weak<RawContext>>,
+    /// Work buffer shared by every node when the graph is computed one node
+    /// at a time, see `step_graph`.
+    step_work: Option<NonNull<ggml_internal::ggml_tensor>>,
+    /// Largest thread count the graph's work buffer has been sized for, see
+    /// `set_threads`.
+    work_threads: i32,
 }
 
 impl ComputationGraph {
+    /// An empty graph computed with `n_threads` threads, capped by
+    /// [`crate::MAX_THREADS_ENV`]. Pass [`crate::default_threads`] to use
+    /// the available parallelism.
     pub fn new(n_threads: i32) -> Self {
         Self {
             inner: ggml_internal::ggml_cgraph {
-                n_threads,
+                n_threads: cap_threads(n_threads.max(1) as usize) as i32,
                 // SAFETY: This should be safe to zero. The original C++ impl
                 // just leaves it uninitialized
                 ..unsafe { std::mem::zeroed::<ggml_internal::ggml_cgraph>() }
             },
+            ctx: Weak::new(),
+            node_owners: Vec::new(),
+            leaf_owners: Vec::new(),
+            step_work: None,
+            work_threads: 0,
         }
     }
 
     pub fn build_forward_expand(&mut self, tensor: &Tensor) {
+        if self.ctx.strong_count() == 0 {
+            self.ctx = Weak::clone(&tensor.ctx);
+        }
         unsafe { ggml_internal::ggml_build_forward_expand(&mut self.inner, tensor.ptr.as_ptr()) }
+        self.record_owners(&tensor.ctx);
+    }
+
+    /// Looks up the context of every node and leaf added since the last
+    /// call, while they are all known to be alive. Tensors of contexts this
+    /// crate did not create are attributed to `fallback`.
+    fn record_owners(&mut self, fallback: &Weak<RawContext>) {
+        let owner = |&raw: &*mut ggml_internal::ggml_tensor| {
+            NonNull::new(raw)
+                .and_then(owner_of)
+                .unwrap_or_else(|| Weak::clone(fallback))
+        };
+        let (n_nodes, n_leafs) = (self.n_nodes(), self.n_leafs());
+        let nodes = &self.inner.nodes[self.node_owners.len()..n_nodes];
+        self.node_owners.extend(nodes.iter().map(owner));
+        let leafs = &self.inner.leafs[self.leaf_owners.len()..n_leafs];
+        self.leaf_owners.extend(leafs.iter().map(owner));
+    }
+
+    /// Builds the graph computing the gradients of `forward`'s output with
+    /// respect to every tensor marked with [`Tensor::set_param`]. The result
+    /// includes the forward pass, so computing it alone is enough.
+    ///
+    /// With `keep`, the gradients of the forward nodes are duplicated first,
+    /// so that `forward` can still be computed on its own afterwards.
+    ///
+    /// Before each computation call [`ComputationGraph::reset`] on the backward
+    /// graph and set the output's gradient to 1, e.g.
+    /// `loss.grad().unwrap().set_f32(1.0)`; the gradients are accumulated
+    /// into otherwise.
+    pub fn build_backward(forward: &mut ComputationGraph, keep: bool) -> Result<Self, Error> {
+        let ctx = forward.ctx.upgrade().ok_or(Error::ContextDropped)?;
+        let inner =
+            unsafe { ggml_internal::ggml_build_backward(ctx.as_ptr(), &mut forward.inner, keep) };
+        let mut backward = Self {
+            inner,
+            ctx: Weak::clone(&forward.ctx),
+            node_owners: Vec::new(),
+            leaf_owners: Vec::new(),
+            step_work: None,
+            work_threads: 0,
+        };
+        backward.record_owners(&forward.ctx);
+        Ok(backward)
+    }
+
+    /// Zeroes the gradients of all nodes in the graph.
+    pub fn reset(&mut self) {
+        unsafe { ggml_internal::ggml_graph_reset(&mut self.inner) }
+    }
+
+    /// Number of computed nodes, i.e. tensors that are the result of an op.
+    pub fn n_nodes(&self) -> usize {
+        self.inner.n_nodes as usize
+    }
+
+    /// Number of leafs: inputs, weights and constants the nodes read from.
+    pub fn n_leafs(&self) -> usize {
+        self.inner.n_leafs as usize
+    }
+
+    /// The computed nodes in evaluation order; the last one is the output
+    /// the graph was last expanded with.
+    pub fn nodes(&self) -> impl Iterator<Item = Tensor> + '_ {
+        (0..self.n_nodes()).filter_map(|i| self.node(i))
+    }
+
+    /// The tensors the graph reads but does not compute.
+    pub fn leafs(&self) -> impl Iterator<Item = Tensor> + '_ {
+        (0..self.n_leafs()).filter_map(|i| self.leaf(i))
+    }
+
+    pub fn node(&self, i: usize) -> Option<Tensor> {
+        let owner = self.node_owners.get(i)?;
+        Some(Tensor::from_raw(self.inner.nodes[i], Weak::clone(owner)))
+    }
+
+    pub fn leaf(&self, i: usize) -> Option<Tensor> {
+        let owner = self.leaf_owners.get(i)?;
+        Some(Tensor::from_raw(self.inner.leafs[i], Weak::clone(owner)))
+    }
+
+    /// Looks up a tensor of the graph by the name set on it. Mostly useful on
+    /// imported graphs, to find the inputs to fill and outputs to read.
+    pub fn get_tensor(&self, name: &str) -> Option<Tensor> {
+        let name = CString::new(crate::io::tensor_name(name).as_ref()).ok()?;
+        // SAFETY: ggml only reads the graph, the pointer cast is for the
+        // non-const parameter in its signature.
+        let raw = unsafe {
+            ggml_internal::ggml_graph_get_tensor(
+                &self.inner as *const _ as *mut ggml_internal::ggml_cgraph,
+                name.as_ptr(),
+            )
+        };
+        let raw = NonNull::new(raw)?;
+        let owner = self.inner.nodes[..self.n_nodes()]
+            .iter()
+            .zip(&self.node_owners)
+            .chain(
+                self.inner.leafs[..self.n_leafs()]
+                    .iter()
+                    .zip(&self.leaf_owners),
+            )
+            .find(|&(&t, _)| t == raw.as_ptr())
+            .map(|(_, owner)| Weak::clone(owner))
+            .or_else(|| owner_of(raw))
+            .unwrap_or_else(|| Weak::clone(&self.ctx));
+        Some(Tensor::from_raw(raw.as_ptr(), owner))
+    }
+
+    /// Writes the graph, including the data of its leafs (weights and
+    /// constants), to a file that [`ComputationGraph::import`] can load
+    /// without the code that built it. An existing file at `path` is only
+    /// replaced once the graph has been written in full.
+    pub fn export(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        c_path(path)?;
+        // ggml only reports a failing `fopen` on stderr, and may stop halfway
+        // through, so write next to `path` and move the file into place once
+        // ggml is done. Creating the file up front checks it can be written.
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| graph_file(path, io::ErrorKind::InvalidInput))?;
+        let mut partial = OsString::from(".");
+        partial.push(file_name);
+        partial.push(format!(".{}.partial", std::process::id()));
+        let partial = path.with_file_name(partial);
+        File::create(&partial).map_err(|e| graph_file(path, e))?;
+        let c_partial = c_path(&partial)?;
+
+        unsafe { ggml_internal::ggml_graph_export(&self.inner, c_partial.as_ptr()) };
+        let written = fs::metadata(&partial).is_ok_and(|m| m.len() > 0);
+        let result = if written {
+            fs::rename(&partial, path).map_err(|e| graph_file(path, e))
+        } else {
+            Err(graph_file(path, io::ErrorKind::WriteZero))
+        };
+        if result.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+        result
+    }
+
+    /// Loads a graph written by [`ComputationGraph::export`]. The leaf data
+    /// and the intermediate nodes are allocated in two new contexts, which
+    /// are returned together with the graph.
+    pub fn import(path: impl AsRef<Path>, n_threads: i32) -> Result<ImportedGraph, Error> {
+        let path = path.as_ref();
+        File::open(path).map_err(|e| graph_file(path, e))?;
+        let c_path = c_path(path)?;
+
+        let mut ctx_data = std::ptr::null_mut();
+        let mut ctx_eval = std::ptr::null_mut();
+        let inner = unsafe {
+            ggml_internal::ggml_graph_import(c_path.as_ptr(), &mut ctx_data, &mut ctx_eval)
+        };
+
+        // ggml creates the data context first, so own whatever it allocated
+        // before bailing out.
+        let data = NonNull::new(ctx_data).map(Context::from_raw);
+        let eval = NonNull::new(ctx_eval).map(Context::from_raw);
+        let (data, eval) = data
+            .zip(eval)
+            .ok_or_else(|| graph_file(path, io::ErrorKind::InvalidData))?;
+
+        let mut graph = ComputationGraph {
+            inner: ggml_internal::ggml_cgraph {
+                n_threads: cap_threads(n_threads.max(1) as usize) as i32,
+                ..inner
+            },
+            ctx: Arc::downgrade(&eval.ptr),
+            node_owners: Vec::new(),
+            leaf_owners: Vec::new(),
+            step_work: None,
+            work_threads: 0,
+        };
+        // The leafs live in the data context and the nodes in the eval one.
+        graph.record_owners(&Arc::downgrade(&eval.ptr));
+        Ok(ImportedGraph { graph, data, eval })
+    }
+
+    /// Panics if any of the graph's tensors are gone, e.g. because they were
+    /// built in a [`crate::Context::with_scratch`] scope that has ended, or
+    /// if the graph would write to a frozen context. Leafs may belong to a
+    /// frozen context as long as they only hold data.
+    pub(crate) fn assert_alive(&self) {
+        const DROPPED: &str = "Computing a graph after the context of its tensors was dropped";
+        for owner in &self.node_owners {
+            match owner.upgrade() {
+                None => panic!("{}", DROPPED),
+                Some(ctx) if ctx.is_frozen() => {
+                    panic!("Cannot compute a graph of a frozen context")
+                }
+                Some(_) => {}
+            }
+        }
+        for (&raw, owner) in self.inner.leafs.iter().zip(&self.leaf_owners) {
+            match owner.upgrade() {
+                None => panic!("{}", DROPPED),
+                Some(ctx) if ctx.is_frozen() => {
+                    if !Tensor::from_raw(raw, Weak::clone(owner)).is_plain_leaf() {
+                        panic!("Cannot compute a graph that writes to a frozen context")
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// Number of threads the graph is computed with by
+    /// [`crate::Context::graph_compute`].
+    pub fn n_threads(&self) -> i32 {
+        self.inner.n_threads
+    }
+
+    /// Sets the number of threads the next computation uses.
+    ///
+    /// ggml sizes the work buffer for the thread count of the first
+    /// computation and reuses it afterwards without checking, while more
+    /// threads may need a larger one. Raising the count past every one the
+    /// buffer was sized for therefore makes ggml allocate a new buffer;
+    /// lower counts keep using the current one, so that alternating between
+    /// counts does not grow the arena.
+    pub(crate) fn set_threads(&mut self, n_threads: i32) {
+        if n_threads > self.work_threads {
+            self.inner.work = std::ptr::null_mut();
+            self.inner.work_size = 0;
+        }
+        self.inner.n_threads = n_threads;
+    }
+
+    /// Notes the thread count ggml sized the work buffer for, to be called
+    /// after every computation of the whole graph.
+    pub(crate) fn record_work(&mut self) {
+        if !self.inner.work.is_null() {
+            self.work_threads = self.work_threads.max(self.inner.n_threads);
+        }
+    }
+
+    /// A scratch single-node graph for [`ComputationGraph::compute_node`],
+    /// computed with `n_threads` threads.
+    ///
+    /// ggml sizes a graph's work buffer and allocates it in the context the
+    /// first time the graph is computed. Doing that for a fresh single-node
+    /// graph on every step would grow the arena each time, so the step graph
+    /// gets one buffer, allocated in `ctx` and kept for later evaluations,
+    /// that is large enough for every node of the graph.
+    pub(crate) fn step_graph(
+        &mut self,
+        ctx: NonNull<ggml_internal::ggml_context>,
+        n_threads: i32,
+    ) -> Box<ggml_internal::ggml_cgraph> {
+        // SAFETY: Same as in `new`.
+        let mut step = Box::new(unsafe { std::mem::zeroed::<ggml_internal::ggml_cgraph>() });
+        step.n_nodes = 1;
+        step.n_threads = n_threads;
+
+        let n_threads = n_threads.max(1) as usize;
+        // SAFETY: Callers check that the graph's tensors are alive.
+        let needed =
+            unsafe { work::graph_work_size(&self.inner.nodes[..self.n_nodes()], n_threads) };
+        if needed == 0 {
+            return step;
+        }
+        // SAFETY: The buffer was allocated by ggml in a context that computes
+        // this graph, and is only reallocated here.
+        let size = self.step_work.map_or(0, |work| unsafe {
+            ggml_internal::ggml_nbytes(work.as_ptr())
+        });
+        if size < needed {
+            let work = unsafe {
+                ggml_internal::ggml_new_tensor_1d(
+                    ctx.as_ptr(),
+                    ggml_internal::ggml_type_GGML_TYPE_I8,
+                    needed as _,
+                )
+            };
+            self.step_work = NonNull::new(work);
+        }
+        if let Some(work) = self.step_work {
+            step.work = work.as_ptr();
+            step.work_size = work::usable_work_size(size.max(needed), n_threads);
+        }
+        step
+    }
+
+    /// Computes only node `i` with a graph from `step_graph`. The node's
+    /// sources must already have been computed.
+    pub(crate) fn compute_node(
+        &self,
+        ctx: NonNull<ggml_internal::ggml_context>,
+        step: &mut ggml_internal::ggml_cgraph,
+        i: usize,
+    ) {
+        step.nodes[0] = self.inner.nodes[i];
+        unsafe { ggml_internal::ggml_graph_compute(ctx.as_ptr(), step) };
+    }
+
+    /// Writes the graph in Graphviz DOT format, for rendering with e.g.
+    /// `dot -Tsvg`.
+    ///
+    /// Every tensor is labelled with its name (if set), op, shape and type,
+    /// and edges are labelled with the operand position. Trainable parameters
+    /// are filled yellow, leafs (inputs and constants) pink and intermediate
+    /// nodes white; the output node is drawn with a bold outline.
+    pub fn to_dot<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "digraph ggml {{")?;
+        writeln!(w, "  rankdir = TB;")?;
+        writeln!(w, "  node [shape = record, style = filled];")?;
+
+        for leaf in self.leafs() {
+            let fill = if leaf.is_param() { "yellow" } else { "pink" };
+            write_dot_node(w, &leaf, fill, false)?;
+        }
+        let n_nodes = self.n_nodes();
+        for (i, node) in self.nodes().enumerate() {
+            let fill = if node.is_param() { "yellow" } else { "white" };
+            write_dot_node(w, &node, fill, i + 1 == n_nodes)?;
+        }
+
+        for node in self.nodes() {
+            for (i, src) in node.sources().iter().enumerate() {
+                writeln!(
+                    w,
+                    "  \"{:p}\" -> \"{:p}\" [label = \"{}\"];",
+                    src.ptr, node.ptr, i
+                )?;
+            }
+        }
+        writeln!(w, "}}")
+    }
+}
+
+/// A graph loaded by [`ComputationGraph::import`], together with the contexts
+/// holding its tensors. The parts are only handed out by reference so that
+/// neither context can be dropped while the graph still points into it.
+pub struct ImportedGraph {
+    graph: ComputationGraph,
+    /// Holds the leafs: weights, constants and inputs.
+    data: Context<'static>,
+    /// Holds the intermediate nodes and the output.
+    eval: Context<'static>,
+}
+
+impl ImportedGraph {
+    pub fn graph(&self) -> &ComputationGraph {
+        &self.graph
+    }
+
+    pub fn data_context(&self) -> &Context<'static> {
+        &self.data
+    }
+
+    pub fn eval_context(&self) -> &Context<'static> {
+        &self.eval
+    }
+
+    /// Looks up an input or output by name, see
+    /// [`ComputationGraph::get_tensor`].
+    pub fn get_tensor(&self, name: &str) -> Option<Tensor> {
+        self.graph.get_tensor(name)
+    }
+
+    pub fn compute(&mut self) {
+        self.eval.graph_compute(&mut self.graph);
+    }
+}
+
+fn graph_file(path: &Path, source: impl Into<IoError>) -> Error {
+    Error::GraphFile {
+        path: path.to_owned(),
+        source: source.into(),
+    }
+}
+
+/// `path` as ggml takes it: UTF-8 without nul characters.
+fn c_path(path: &Path) -> Result<CString, Error> {
+    path.to_str()
+        .and_then(|p| CString::new(p).ok())
+        .ok_or_else(|| graph_file(path, io::ErrorKind::InvalidInput))
+}
+
+fn write_dot_node<W: io::Write>(
+    w: &mut W,
+    tensor: &Tensor,
+    fill: &str,
+    is_output: bool,
+) -> io::Result<()> {
+    let name = tensor.name();
+
+    let ne = tensor.get_ne();
+    let rank = ne.iter().rposition(|&n| n != 1).map_or(1, |i| i + 1);
+    let shape = ne[..rank]
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<_>>()
+        .join(" x ");
+
+    let mut label = String::new();
+    if !name.is_empty() {
+        label.push_str(&escape_dot_record(&name));
+        label.push('|');
+    }
+    label.push_str(&format!(
+        "{}|{}|{:?}",
+        tensor.op().name(),
+        shape,
+        tensor.get_type()
+    ));
+
+    writeln!(
+        w,
+        "  \"{:p}\" [label = \"{{{}}}\", fillcolor = {}{}];",
+        tensor.ptr,
+        label,
+        fill,
+        if is_output { ", penwidth = 3" } else { "" }
+    )
+}
+
+/// Escapes the characters that are special inside a DOT record label.
+fn escape_dot_record(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '"' | '\\' | '{' | '}' | '|' | '<' | '>') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
     }
+    escaped
 }
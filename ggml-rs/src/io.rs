@@ -1,6 +1,8 @@
 use crate::context::Context;
+use crate::error::Error;
 use crate::tensor::{Dimension, Tensor};
-pub use ggml_io::{static_tensor, ModelIO};
+pub use ggml_io::{static_tensor, ModelIO, StaticLayer, TensorNames};
+use std::borrow::Cow;
 use std::io::Error as IoError;
 
 pub enum ModelIOError {
@@ -25,4 +27,127 @@ pub trait ModelIO: Sized {
         shape: Vec<Option<usize>>,
     ) -> Result<Tensor, ()>;
     fn write(&self, path: &str) -> Result<(), ModelIOError>;
+
+    /// Like [`ModelIO::read_to_tensor`], naming the tensor `name`, e.g. a
+    /// path built with [`tensor_path`].
+    fn read_to_named_tensor<R: std::io::Read>(
+        ctx: &Context,
+        reader: &mut R,
+        name: &str,
+        dim: Dimension,
+        shape: Vec<Option<usize>>,
+    ) -> Result<Tensor, ()> {
+        let tensor = Self::read_to_tensor(ctx, reader, dim, shape)?;
+        tensor.set_name(name).map_err(|_| ())?;
+        Ok(tensor)
+    }
+}
+
+/// Names the tensors of a model after their place in it, e.g.
+/// `layers.3.attn.wq` for the `wq` field of the `attn` field of the fourth
+/// element of `layers`, so that debug output and graph dumps show where a
+/// tensor comes from and weights can be found with [`Context::get_tensor`].
+///
+/// `#[derive(TensorNames)]` implements it for structs whose fields are
+/// tensors or themselves implement it; tuple struct fields are named by
+/// index.
+pub trait TensorNames {
+    /// Names every tensor below `path`. An empty path names fields by their
+    /// own name.
+    fn set_names(&self, path: &str) -> Result<(), Error>;
+}
+
+/// Reads the tensors of a layer stored field after field, e.g. the weights
+/// of a model file, naming each after its path below `path` like
+/// [`TensorNames`] does.
+///
+/// `#[static_tensor]` types implement it by reading a single tensor, and
+/// `#[derive(StaticLayer)]` implements it for structs whose fields implement
+/// it, reading the fields in declaration order.
+pub trait StaticLayer {
+    fn read_layer<R: std::io::Read>(
+        ctx: &Context,
+        reader: &mut R,
+        path: &str,
+    ) -> Result<Vec<Tensor>, ()>;
+}
+
+impl<T: StaticLayer, const N: usize> StaticLayer for [T; N] {
+    fn read_layer<R: std::io::Read>(
+        ctx: &Context,
+        reader: &mut R,
+        path: &str,
+    ) -> Result<Vec<Tensor>, ()> {
+        let mut tensors = Vec::new();
+        for i in 0..N {
+            tensors.extend(T::read_layer(
+                ctx,
+                reader,
+                &tensor_path(path, &i.to_string()),
+            )?);
+        }
+        Ok(tensors)
+    }
+}
+
+/// Appends `segment` to a dotted tensor path.
+pub fn tensor_path(path: &str, segment: &str) -> String {
+    if path.is_empty() {
+        segment.to_owned()
+    } else {
+        format!("{}.{}", path, segment)
+    }
+}
+
+/// The name a tensor at `path` is given by [`TensorNames`] and
+/// [`StaticLayer`]. Paths that fit in [`Tensor::MAX_NAME_LEN`] bytes are
+/// used as they are; longer ones are cut to a prefix followed by `~` and a
+/// hash of the whole path, so that distinct paths keep distinct names.
+/// [`Context::get_tensor`] applies the same to the name it looks up, so
+/// tensors can be found by their full path either way.
+pub fn tensor_name(path: &str) -> Cow<'_, str> {
+    if path.len() <= Tensor::MAX_NAME_LEN {
+        return Cow::Borrowed(path);
+    }
+    // 32-bit FNV-1a, which is stable across platforms and releases.
+    let hash = path.bytes().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    });
+    let mut end = Tensor::MAX_NAME_LEN - "~01234567".len();
+    while !path.is_char_boundary(end) {
+        end -= 1;
+    }
+    Cow::Owned(format!("{}~{:08x}", &path[..end], hash))
+}
+
+impl TensorNames for Tensor {
+    fn set_names(&self, path: &str) -> Result<(), Error> {
+        self.set_name(&tensor_name(path))
+    }
+}
+
+impl<T: TensorNames> TensorNames for [T] {
+    fn set_names(&self, path: &str) -> Result<(), Error> {
+        self.iter()
+            .enumerate()
+            .try_for_each(|(i, item)| item.set_names(&tensor_path(path, &i.to_string())))
+    }
+}
+
+impl<T: TensorNames, const N: usize> TensorNames for [T; N] {
+    fn set_names(&self, path: &str) -> Result<(), Error> {
+        self.as_slice().set_names(path)
+    }
+}
+
+impl<T: TensorNames> TensorNames for Vec<T> {
+    fn set_names(&self, path: &str) -> Result<(), Error> {
+        self.as_slice().set_names(path)
+    }
+}
+
+impl<T: TensorNames> TensorNames for Option<T> {
+    fn set_names(&self, path: &str) -> Result<(), Error> {
+        self.as_ref().map_or(Ok(()), |item| item.set_names(path))
+    }
 }
@@ -1,11 +1,42 @@
+#[cfg(feature = "ndarray")]
+mod array;
+mod compare;
+mod compiled;
+mod compute;
 mod context;
+mod display;
+mod error;
 mod graph;
 pub mod io;
+mod memory;
 pub extern crate bincode;
+#[cfg(feature = "ndarray")]
+pub extern crate ndarray;
 
 mod ops;
+mod optim;
+mod profile;
+mod shared;
+mod slice;
+mod stats;
 mod tensor;
+mod work;
 
-pub use context::Context;
-pub use graph::ComputationGraph;
-pub use tensor::{DataType, Dimension, Tensor};
+pub use compare::{Comparison, Expected, Mismatch};
+pub use compiled::CompiledGraph;
+pub use compute::{
+    default_threads, CancellationToken, ComputeHandle, ComputeOptions, MAX_THREADS_ENV,
+};
+pub use context::{Context, ContextBuilder, ARENA_ALIGN};
+pub use display::TensorDisplay;
+pub use error::{Error, IoError};
+pub use graph::{ComputationGraph, ImportedGraph};
+pub use memory::{MemoryReport, TensorMemory};
+pub use ops::RopeMode;
+pub use optim::{
+    AdamParams, LbfgsParams, LineSearch, OptOutcome, OptProgress, OptResult, Optimizer,
+};
+pub use profile::{NodeProfile, OpProfile, ProfileReport};
+pub use shared::{SharedContext, SharedTensor};
+pub use stats::TensorStats;
+pub use tensor::{DataType, Dimension, Element, OpKind, Tensor};
@@ -0,0 +1,198 @@
+use crate::compute::cap_threads;
+use crate::context::{Context, ContextBuilder};
+use crate::error::Error;
+use crate::tensor::{DataType, OpKind, Tensor};
+use crate::work;
+use std::collections::HashSet;
+use std::fmt;
+use std::ptr::NonNull;
+use std::sync::{Arc, Weak};
+
+/// Arena usage of one tensor, see [`Context::plan`].
+#[derive(Clone, Debug)]
+pub struct TensorMemory {
+    pub name: String,
+    pub op: OpKind,
+    pub dtype: DataType,
+    pub shape: [i32; 4],
+    /// Bytes of element data, padded to ggml's alignment. Zero for views,
+    /// which share the data of the tensor they look into.
+    pub data_size: usize,
+    /// Bytes ggml needs for the tensor's bookkeeping, the same for every
+    /// tensor.
+    pub overhead: usize,
+}
+
+impl TensorMemory {
+    pub fn size(&self) -> usize {
+        self.data_size + self.overhead
+    }
+}
+
+/// The arena size a set of tensors needs, from a dry run by
+/// [`Context::plan`].
+#[derive(Clone, Debug, Default)]
+pub struct MemoryReport {
+    /// Every tensor in creation order, including the small parameter
+    /// tensors some ops create internally.
+    pub tensors: Vec<TensorMemory>,
+    /// Bytes of the work buffer ggml allocates in the arena the first time
+    /// the planned graph is computed, for the thread count passed to
+    /// [`Context::plan`]. Zero if no op needs one.
+    pub work_size: usize,
+    /// Bookkeeping of the work buffer's tensor, zero without a buffer.
+    pub work_overhead: usize,
+}
+
+impl MemoryReport {
+    /// The `mem_size` to pass to [`Context::init`] to build the same
+    /// tensors for real and compute them in the same context, with at most
+    /// the planned number of threads.
+    pub fn total(&self) -> usize {
+        let tensors: usize = self.tensors.iter().map(TensorMemory::size).sum();
+        tensors + padded(self.work_size) + self.work_overhead
+    }
+}
+
+/// Prints one line per tensor followed by the total.
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for tensor in &self.tensors {
+            writeln!(
+                f,
+                "  {:<24} {:<14} {:<5?} {:>24} {:>12} bytes",
+                tensor.name,
+                tensor.op.name(),
+                tensor.dtype,
+                format!("{:?}", tensor.shape),
+                tensor.size()
+            )?;
+        }
+        if self.work_size > 0 {
+            writeln!(
+                f,
+                "  {:<24} {:>58} bytes",
+                "work buffer",
+                padded(self.work_size) + self.work_overhead
+            )?;
+        }
+        write!(f, "{} tensors, {} bytes", self.tensors.len(), self.total())
+    }
+}
+
+/// Ops whose result points into the data of an operand instead of owning
+/// any. `cpy` returns a view of its destination.
+fn is_view(op: OpKind) -> bool {
+    matches!(
+        op,
+        OpKind::View | OpKind::Reshape | OpKind::Permute | OpKind::Transpose | OpKind::Cpy
+    )
+}
+
+fn padded(size: usize) -> usize {
+    let align = ggml_internal::GGML_MEM_ALIGN as usize;
+    size.div_ceil(align) * align
+}
+
+/// Tensors created by the dry run of [`Context::plan`].
+pub(crate) struct Recording {
+    pub(crate) tensors: Vec<NonNull<ggml_internal::ggml_tensor>>,
+    /// The `max_tensors` the dry run's arena is sized for.
+    max: usize,
+}
+
+impl Recording {
+    /// Fails once `max` tensors have been created, while the arena still
+    /// has room for ggml to create the next one with its internal tensors.
+    pub(crate) fn check_room(&self) -> Result<(), Error> {
+        if self.tensors.len() < self.max {
+            Ok(())
+        } else {
+            Err(Error::TooManyTensors { max: self.max })
+        }
+    }
+}
+
+impl Context<'_> {
+    /// Measures the arena a model needs without allocating it.
+    ///
+    /// `build` is run against a context created in ggml's no-alloc mode, in
+    /// which tensors get their shape and type but no data. It should create
+    /// the same tensors and ops as the real model, up to and including the
+    /// graph. The report includes the work buffer computing the ops with
+    /// `n_threads` threads (capped like [`crate::ComputationGraph::new`])
+    /// needs.
+    ///
+    /// At most `max_tensors` tensors can be created; more fail with
+    /// [`Error::TooManyTensors`], or panic in the constructors that return
+    /// no `Result`. Tensors of the dry run have no data: `build` must not
+    /// read, write or compute them.
+    pub fn plan(
+        max_tensors: usize,
+        n_threads: usize,
+        build: impl FnOnce(&Context<'_>) -> Result<(), Error>,
+    ) -> Result<MemoryReport, Error> {
+        let overhead = unsafe { ggml_internal::ggml_tensor_overhead() };
+        // Ops may create up to two small tensors for their parameters
+        // besides their result, and allocate their data even in no-alloc
+        // mode, so leave room for those on top of every tensor.
+        let mem_size = max_tensors * 3 * (overhead + 4 * ggml_internal::GGML_MEM_ALIGN as usize);
+        let ctx = ContextBuilder::new(mem_size).no_alloc(true).build()?;
+        *ctx.ptr.recorded.lock().unwrap() = Some(Recording {
+            tensors: Vec::new(),
+            max: max_tensors,
+        });
+
+        build(&ctx)?;
+
+        let recorded = ctx
+            .ptr
+            .recorded
+            .lock()
+            .unwrap()
+            .take()
+            .map(|r| r.tensors)
+            .unwrap_or_default();
+        let weak = Arc::downgrade(&ctx.ptr);
+        let mut seen = HashSet::new();
+        let mut pending: Vec<Tensor> = recorded
+            .into_iter()
+            .rev()
+            .map(|ptr| Tensor::from_raw(ptr.as_ptr(), Weak::clone(&weak)))
+            .collect();
+        let mut report = MemoryReport::default();
+        let mut nodes = Vec::new();
+
+        // The recorded tensors in creation order, each followed by any
+        // operands that were not created through the bindings.
+        while let Some(tensor) = pending.pop() {
+            if !seen.insert(tensor.ptr) {
+                continue;
+            }
+            let op = tensor.op();
+            if op != OpKind::None {
+                nodes.push(tensor.ptr.as_ptr());
+            }
+            report.tensors.push(TensorMemory {
+                name: tensor.name(),
+                op,
+                dtype: tensor.get_type(),
+                shape: tensor.get_ne(),
+                data_size: if is_view(op) {
+                    0
+                } else {
+                    padded(tensor.nbytes())
+                },
+                overhead,
+            });
+            pending.extend(tensor.sources().into_iter().rev());
+        }
+
+        // SAFETY: `ctx` keeps the dry run's tensors alive.
+        report.work_size = unsafe { work::graph_work_size(&nodes, cap_threads(n_threads)) };
+        if report.work_size > 0 {
+            report.work_overhead = overhead;
+        }
+        Ok(report)
+    }
+}
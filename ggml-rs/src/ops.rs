@@ -1 +1,491 @@
+use crate::context::{allocation_handle, Context};
+use crate::error::Error;
+use crate::tensor::{DataType, Dimension, Tensor};
+use std::any::Any;
+use std::cell::RefCell;
+use std::ops::{Add, Mul, Neg, Sub};
+use std::os::raw::c_int;
+use std::panic::{self, AssertUnwindSafe};
 
+/// Which dimensions rotary position embedding rotates together.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RopeMode {
+    /// Adjacent pairs `(x0, x1), (x2, x3), ...`, as in the original LLaMA.
+    #[default]
+    Normal,
+    /// The first half of the rotated dimensions paired with the second
+    /// half, as in GPT-NeoX and GPT-J style checkpoints.
+    NeoX,
+    /// ChatGLM's 2-D positional scheme, which also uses the context length,
+    /// see [`Context::op_rope_with_n_ctx`].
+    Glm,
+}
+
+impl From<RopeMode> for i32 {
+    fn from(mode: RopeMode) -> Self {
+        match mode {
+            RopeMode::Normal => 0,
+            RopeMode::NeoX => 2,
+            RopeMode::Glm => 4,
+        }
+    }
+}
+
+/// Length of one spatial axis after a convolution window has been slid over
+/// it.
+fn window_output_size(
+    input: usize,
+    kernel: usize,
+    stride: usize,
+    padding: usize,
+    dilation: usize,
+) -> Option<usize> {
+    if kernel == 0 || stride == 0 || dilation == 0 {
+        return None;
+    }
+    let span = dilation * (kernel - 1) + 1;
+    (input + 2 * padding)
+        .checked_sub(span)
+        .map(|rest| rest / stride + 1)
+}
+
+fn ne(t: &Tensor) -> [usize; 4] {
+    t.get_ne().map(|n| n as usize)
+}
+
+fn invalid(op: &'static str, reason: &'static str) -> Error {
+    Error::InvalidOperands { op, reason }
+}
+
+/// Convolution. Spatial axes come first, as everywhere else in ggml: a 1-D
+/// signal is `[length, channels]` and an image is
+/// `[width, height, channels, batch]`.
+///
+/// The output shape is computed up front, so an incompatible kernel/input
+/// pair or a stride/padding combination that ggml does not implement is
+/// reported as [`Error::InvalidOperands`] instead of tripping an assertion
+/// inside ggml.
+impl Context<'_> {
+    /// 1-D convolution of `input` (`[length, in_channels]`, F32) with
+    /// `kernel` (`[kernel_size, in_channels, out_channels]`, F16 or F32),
+    /// producing `[out_length, out_channels]`.
+    ///
+    /// ggml only implements odd kernel sizes with half padding
+    /// (`padding == kernel_size / 2`), no dilation and strides of 1 or 2,
+    /// which covers the Whisper encoder stem. A stride of 2 gives
+    /// `length / 2` outputs, rounded down. See [`Context::op_conv_1d_ph`]
+    /// for the common case.
+    pub fn op_conv_1d(
+        &self,
+        kernel: &Tensor,
+        input: &Tensor,
+        stride: usize,
+        padding: usize,
+        dilation: usize,
+    ) -> Result<Tensor, Error> {
+        const OP: &str = "conv_1d";
+        self.check_operands(OP, &[kernel, input])?;
+        let [k, k_in, k_out, k3] = ne(kernel);
+        let [len, in_channels, i2, i3] = ne(input);
+
+        if k3 != 1 || i2 != 1 || i3 != 1 {
+            return Err(invalid(OP, "expected a 3-D kernel and a 2-D input"));
+        }
+        if k_in != in_channels {
+            return Err(invalid(OP, "kernel and input channel counts differ"));
+        }
+        if k % 2 == 0 || padding != k / 2 || dilation != 1 {
+            return Err(invalid(
+                OP,
+                "ggml only implements odd kernels with half padding and no dilation",
+            ));
+        }
+        let conv: BinaryOpFn = match stride {
+            1 => ggml_internal::ggml_conv_1d_s1_ph,
+            2 => ggml_internal::ggml_conv_1d_s2_ph,
+            _ => return Err(invalid(OP, "ggml only implements strides 1 and 2")),
+        };
+        if len < k {
+            return Err(invalid(OP, "kernel is longer than the input"));
+        }
+        let out_len = len / stride;
+
+        let tensor = unsafe { conv(self.ptr.as_ptr(), kernel.ptr.as_ptr(), input.ptr.as_ptr()) };
+        Ok(self.new_tensor_raw(tensor, Dimension::D2, [out_len, k_out, 1, 1]))
+    }
+
+    /// 1-D convolution with half padding, so that a stride of 1 preserves the
+    /// input length and a stride of 2 halves it.
+    pub fn op_conv_1d_ph(
+        &self,
+        kernel: &Tensor,
+        input: &Tensor,
+        stride: usize,
+    ) -> Result<Tensor, Error> {
+        let padding = ne(kernel)[0] / 2;
+        self.op_conv_1d(kernel, input, stride, padding, 1)
+    }
+
+    /// 2-D convolution of `input` (`[width, height, in_channels]`) with
+    /// `kernel` (`[kernel_w, kernel_h, in_channels, out_channels]`),
+    /// producing `[out_w, out_h, out_channels]`.
+    ///
+    /// ggml only implements non-overlapping windows over a single image:
+    /// the stride must equal the kernel size, without padding or dilation
+    /// (patch embeddings).
+    pub fn op_conv_2d(
+        &self,
+        kernel: &Tensor,
+        input: &Tensor,
+        stride: (usize, usize),
+        padding: (usize, usize),
+        dilation: (usize, usize),
+    ) -> Result<Tensor, Error> {
+        const OP: &str = "conv_2d";
+        self.check_operands(OP, &[kernel, input])?;
+        let [kw, kh, k_in, k_out] = ne(kernel);
+        let [w, h, in_channels, batch] = ne(input);
+
+        if k_in != in_channels {
+            return Err(invalid(OP, "kernel and input channel counts differ"));
+        }
+        if batch != 1 {
+            return Err(invalid(OP, "ggml only implements a batch of one image"));
+        }
+        if stride != (kw, kh) || padding != (0, 0) || dilation != (1, 1) {
+            return Err(invalid(
+                OP,
+                "ggml only implements stride == kernel size without padding or dilation",
+            ));
+        }
+        let out_w = window_output_size(w, kw, stride.0, padding.0, dilation.0)
+            .ok_or_else(|| invalid(OP, "kernel is wider than the input"))?;
+        let out_h = window_output_size(h, kh, stride.1, padding.1, dilation.1)
+            .ok_or_else(|| invalid(OP, "kernel is taller than the input"))?;
+
+        let tensor = unsafe {
+            ggml_internal::ggml_conv_2d_sk_p0(
+                self.ptr.as_ptr(),
+                kernel.ptr.as_ptr(),
+                input.ptr.as_ptr(),
+            )
+        };
+        Ok(self.new_tensor_raw(tensor, Dimension::D3, [out_w, out_h, k_out, 1]))
+    }
+}
+
+thread_local! {
+    /// First panic raised by a map op during the computation running on this
+    /// thread. ggml calls map ops through a C function pointer, so the panic
+    /// is caught in the trampoline and re-raised once ggml has returned. At
+    /// the pinned revision ggml runs map ops as a single task on the thread
+    /// that called `ggml_graph_compute`, so each computation has its own slot
+    /// and concurrent computations on other threads cannot take its panic.
+    static MAP_OP_PANIC: RefCell<Option<Box<dyn Any + Send>>> = const { RefCell::new(None) };
+}
+
+fn catch_map_op_panic(dst: &mut [f32], f: impl FnOnce(&mut [f32])) {
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| f(&mut *dst))) {
+        dst.fill(f32::NAN);
+        MAP_OP_PANIC.with(|slot| {
+            slot.borrow_mut().get_or_insert(payload);
+        });
+    }
+}
+
+/// Re-raises a panic caught inside a map op of the computation that just
+/// finished on this thread, if any.
+pub(crate) fn resume_map_op_panic() {
+    if let Some(payload) = MAP_OP_PANIC.with(|slot| slot.borrow_mut().take()) {
+        panic::resume_unwind(payload);
+    }
+}
+
+/// Conjures the function a trampoline was instantiated for.
+///
+/// # Safety
+/// `F` must be zero-sized, which `op_map_unary`/`op_map_binary` check at
+/// compile time. A zero-sized closure or function item carries no state, so
+/// every instance is the same value.
+unsafe fn zst_instance<F>() -> F {
+    std::ptr::NonNull::<F>::dangling().as_ptr().read()
+}
+
+unsafe extern "C" fn map_unary_trampoline<F>(n: c_int, dst: *mut f32, src: *const f32)
+where
+    F: Fn(&[f32], &mut [f32]),
+{
+    let f = zst_instance::<F>();
+    let src = std::slice::from_raw_parts(src, n as usize);
+    let dst = std::slice::from_raw_parts_mut(dst, n as usize);
+    catch_map_op_panic(dst, |dst| f(src, dst));
+}
+
+unsafe extern "C" fn map_binary_trampoline<F>(
+    n: c_int,
+    dst: *mut f32,
+    src0: *const f32,
+    src1: *const f32,
+) where
+    F: Fn(&[f32], &[f32], &mut [f32]),
+{
+    let f = zst_instance::<F>();
+    let src0 = std::slice::from_raw_parts(src0, n as usize);
+    let src1 = std::slice::from_raw_parts(src1, n as usize);
+    let dst = std::slice::from_raw_parts_mut(dst, n as usize);
+    catch_map_op_panic(dst, |dst| f(src0, src1, dst));
+}
+
+/// User-defined element-wise ops, for experimenting with activations ggml
+/// does not ship.
+///
+/// ggml hands a map op no user data, only a function pointer, so `f` has to
+/// be a plain `fn` or a closure that captures nothing; passing anything else
+/// fails to compile. `f` is called once per row with the input row(s) and
+/// the output row to fill. ggml computes map ops as a single task on the
+/// thread computing the graph, whatever its thread count, so the rows are
+/// processed one after the other. `f` must still be `Send + Sync` because
+/// that thread need not be the one that built the op, see
+/// [`Context::graph_compute_background`].
+///
+/// A panic inside `f` does not unwind into ggml: the affected row is filled
+/// with NaN and the panic is resumed by [`Context::graph_compute`] once the
+/// computation has finished.
+impl Context<'_> {
+    /// Applies `f` row by row to the F32 tensor `a`.
+    pub fn op_map_unary<F>(&self, a: &Tensor, f: F) -> Result<Tensor, Error>
+    where
+        F: Fn(&[f32], &mut [f32]) + Copy + Send + Sync + 'static,
+    {
+        const {
+            assert!(
+                std::mem::size_of::<F>() == 0,
+                "map ops only accept functions and closures without captures"
+            )
+        };
+        let _ = f;
+
+        const OP: &str = "map_unary";
+        self.check_operands(OP, &[a])?;
+        if !matches!(a.get_type(), DataType::F32) {
+            return Err(invalid(OP, "map ops only support F32 tensors"));
+        }
+
+        let tensor = unsafe {
+            ggml_internal::ggml_map_unary_f32(
+                self.ptr.as_ptr(),
+                a.ptr.as_ptr(),
+                Some(map_unary_trampoline::<F>),
+            )
+        };
+        Ok(self.new_tensor_raw(tensor, a.dim.clone(), a.shape))
+    }
+
+    /// Applies `f` row by row to the F32 tensors `a` and `b`, which must have
+    /// the same shape.
+    pub fn op_map_binary<F>(&self, a: &Tensor, b: &Tensor, f: F) -> Result<Tensor, Error>
+    where
+        F: Fn(&[f32], &[f32], &mut [f32]) + Copy + Send + Sync + 'static,
+    {
+        const {
+            assert!(
+                std::mem::size_of::<F>() == 0,
+                "map ops only accept functions and closures without captures"
+            )
+        };
+        let _ = f;
+
+        const OP: &str = "map_binary";
+        self.check_operands(OP, &[a, b])?;
+        if !matches!(a.get_type(), DataType::F32) || !matches!(b.get_type(), DataType::F32) {
+            return Err(invalid(OP, "map ops only support F32 tensors"));
+        }
+        if a.get_ne() != b.get_ne() {
+            return Err(invalid(OP, "operands must have the same shape"));
+        }
+
+        let tensor = unsafe {
+            ggml_internal::ggml_map_binary_f32(
+                self.ptr.as_ptr(),
+                a.ptr.as_ptr(),
+                b.ptr.as_ptr(),
+                Some(map_binary_trampoline::<F>),
+            )
+        };
+        Ok(self.new_tensor_raw(tensor, a.dim.clone(), a.shape))
+    }
+}
+
+/// Positional encodings beyond the plain [`Context::op_rope`].
+impl Context<'_> {
+    /// Rotary position embedding that also passes the context length
+    /// `n_ctx`, which [`RopeMode::Glm`] needs; the other modes ignore it.
+    pub fn op_rope_with_n_ctx(
+        &self,
+        a: &Tensor,
+        npast: i32,
+        ndims: i32,
+        mode: RopeMode,
+        n_ctx: i32,
+    ) -> Result<Tensor, Error> {
+        self.check_operands("rope", &[a])?;
+        let tensor = unsafe {
+            ggml_internal::ggml_rope(
+                self.ptr.as_ptr(),
+                a.ptr.as_ptr(),
+                npast,
+                ndims,
+                mode.into(),
+                n_ctx,
+            )
+        };
+        Ok(self.new_tensor_raw(tensor, a.dim.clone(), a.shape))
+    }
+
+    /// Attention with linear biases: adds a per-head linear penalty on the
+    /// key distance to the attention scores `a` (`[n_kv, n_tokens, n_head]`)
+    /// instead of encoding positions into the embeddings. `bias_max` is the
+    /// largest slope, 8.0 in the ALiBi paper.
+    pub fn op_alibi(
+        &self,
+        a: &Tensor,
+        npast: i32,
+        n_head: i32,
+        bias_max: f32,
+    ) -> Result<Tensor, Error> {
+        self.check_operands("alibi", &[a])?;
+        let tensor = unsafe {
+            ggml_internal::ggml_alibi(self.ptr.as_ptr(), a.ptr.as_ptr(), npast, n_head, bias_max)
+        };
+        Ok(self.new_tensor_raw(tensor, a.dim.clone(), a.shape))
+    }
+}
+
+type BinaryOpFn = unsafe extern "C" fn(
+    *mut ggml_internal::ggml_context,
+    *mut ggml_internal::ggml_tensor,
+    *mut ggml_internal::ggml_tensor,
+) -> *mut ggml_internal::ggml_tensor;
+
+type UnaryOpFn = unsafe extern "C" fn(
+    *mut ggml_internal::ggml_context,
+    *mut ggml_internal::ggml_tensor,
+) -> *mut ggml_internal::ggml_tensor;
+
+/// Graph building straight from tensors, so model code can be written as
+/// `&a * &b + &c` instead of nested `ctx.op_*` calls. The new node is
+/// created in the context owning the operands. One operand may instead be a
+/// leaf of a frozen context (see [`crate::Context::freeze`]), in which case
+/// the node is created in the context of the other one.
+///
+/// # Panics
+/// If the operands belong to different contexts, the owning context has
+/// been dropped, every operand belongs to a frozen context, or the dry run
+/// of [`crate::Context::plan`] has created as many tensors as it allows.
+impl Tensor {
+    fn unary_node(&self, build: UnaryOpFn) -> Tensor {
+        let ctx = self
+            .ctx
+            .upgrade()
+            .expect("Using a tensor after the context was dropped");
+        assert!(!ctx.is_frozen(), "Cannot build nodes in a frozen context");
+        ctx.assert_room();
+        let raw = unsafe { build(ctx.as_ptr(), self.ptr.as_ptr()) };
+        let node = Tensor::from_raw(raw, allocation_handle(&ctx));
+        ctx.record(node.ptr);
+        node
+    }
+
+    fn binary_node(&self, rhs: &Tensor, name: &str, build: BinaryOpFn) -> Tensor {
+        let (ctx, rhs_ctx) = match (self.ctx.upgrade(), rhs.ctx.upgrade()) {
+            (Some(ctx), Some(rhs_ctx)) => (ctx, rhs_ctx),
+            _ => panic!("Using a tensor after the context was dropped"),
+        };
+        let ctx = match (ctx.is_frozen(), rhs_ctx.is_frozen()) {
+            (false, false) => {
+                assert!(
+                    ctx.raw == rhs_ctx.raw,
+                    "Cannot {} tensors that belong to different contexts",
+                    name
+                );
+                ctx
+            }
+            (true, false) if self.is_plain_leaf() => rhs_ctx,
+            (false, true) if rhs.is_plain_leaf() => ctx,
+            (true, true) => panic!("Cannot build nodes in a frozen context"),
+            _ => panic!(
+                "Cannot {} tensors of a frozen context that are not leaves",
+                name
+            ),
+        };
+        ctx.assert_room();
+        let raw = unsafe { build(ctx.as_ptr(), self.ptr.as_ptr(), rhs.ptr.as_ptr()) };
+        let node = Tensor::from_raw(raw, allocation_handle(&ctx));
+        ctx.record(node.ptr);
+        node
+    }
+
+    /// Matrix multiplication with ggml's operand convention: for `self` of
+    /// shape `[k, m]` and `rhs` of shape `[k, n]` the result has shape
+    /// `[m, n]`, i.e. every row of `rhs` is multiplied by `self` transposed.
+    /// This matches the layout weights are stored in.
+    pub fn matmul(&self, rhs: &Tensor) -> Tensor {
+        self.binary_node(rhs, "matmul", ggml_internal::ggml_mul_mat)
+    }
+}
+
+macro_rules! impl_binary_operator {
+    ($trait:ident, $method:ident, $ggml_fn:path) => {
+        impl $trait<&Tensor> for &Tensor {
+            type Output = Tensor;
+
+            fn $method(self, rhs: &Tensor) -> Tensor {
+                self.binary_node(rhs, stringify!($method), $ggml_fn)
+            }
+        }
+
+        impl $trait<Tensor> for &Tensor {
+            type Output = Tensor;
+
+            fn $method(self, rhs: Tensor) -> Tensor {
+                self.binary_node(&rhs, stringify!($method), $ggml_fn)
+            }
+        }
+
+        impl $trait<&Tensor> for Tensor {
+            type Output = Tensor;
+
+            fn $method(self, rhs: &Tensor) -> Tensor {
+                self.binary_node(rhs, stringify!($method), $ggml_fn)
+            }
+        }
+
+        impl $trait<Tensor> for Tensor {
+            type Output = Tensor;
+
+            fn $method(self, rhs: Tensor) -> Tensor {
+                self.binary_node(&rhs, stringify!($method), $ggml_fn)
+            }
+        }
+    };
+}
+
+impl_binary_operator!(Add, add, ggml_internal::ggml_add);
+impl_binary_operator!(Sub, sub, ggml_internal::ggml_sub);
+impl_binary_operator!(Mul, mul, ggml_internal::ggml_mul);
+
+impl Neg for &Tensor {
+    type Output = Tensor;
+
+    fn neg(self) -> Tensor {
+        self.unary_node(ggml_internal::ggml_neg)
+    }
+}
+
+impl Neg for Tensor {
+    type Output = Tensor;
+
+    fn neg(self) -> Tensor {
+        self.unary_node(ggml_internal::ggml_neg)
+    }
+}
@@ -0,0 +1,378 @@
+use crate::context::{Context, RawContext};
+use crate::error::Error;
+use crate::graph::ComputationGraph;
+use crate::tensor::Tensor;
+use std::fmt;
+use std::ops::ControlFlow;
+use std::sync::{Arc, Weak};
+
+/// Parameters of the Adam optimizer. The defaults are ggml's.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdamParams {
+    /// Maximum number of iterations.
+    pub iterations: u32,
+    pub learning_rate: f32,
+    pub beta1: f32,
+    pub beta2: f32,
+    /// Added to the denominator of the update for numerical stability.
+    pub eps: f32,
+    /// Converged once the relative change of the loss drops below this.
+    pub eps_f: f32,
+    /// Converged once the gradient norm drops below this.
+    pub eps_g: f32,
+}
+
+impl Default for AdamParams {
+    fn default() -> Self {
+        let adam = default_params(ggml_internal::ggml_opt_type_GGML_OPT_ADAM).adam;
+        Self {
+            iterations: adam.n_iter as u32,
+            learning_rate: adam.alpha,
+            beta1: adam.beta1,
+            beta2: adam.beta2,
+            eps: adam.eps,
+            eps_f: adam.eps_f,
+            eps_g: adam.eps_g,
+        }
+    }
+}
+
+/// Condition a step of [`LbfgsParams`]'s line search has to satisfy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum LineSearch {
+    Armijo = ggml_internal::ggml_linesearch_GGML_LINESEARCH_BACKTRACKING_ARMIJO,
+    Wolfe = ggml_internal::ggml_linesearch_GGML_LINESEARCH_BACKTRACKING_WOLFE,
+    StrongWolfe = ggml_internal::ggml_linesearch_GGML_LINESEARCH_BACKTRACKING_STRONG_WOLFE,
+}
+
+/// Parameters of the L-BFGS optimizer. The defaults are ggml's.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LbfgsParams {
+    /// Number of past updates used to approximate the Hessian.
+    pub memory: u32,
+    /// Maximum number of iterations.
+    pub iterations: u32,
+    /// Maximum number of line search steps per iteration.
+    pub max_linesearch: u32,
+    /// Converged once the gradient norm relative to the parameter norm drops
+    /// below this.
+    pub eps: f32,
+    pub ftol: f32,
+    pub wolfe: f32,
+    pub min_step: f32,
+    pub max_step: f32,
+    pub linesearch: LineSearch,
+}
+
+impl Default for LbfgsParams {
+    fn default() -> Self {
+        let lbfgs = default_params(ggml_internal::ggml_opt_type_GGML_OPT_LBFGS).lbfgs;
+        Self {
+            memory: lbfgs.m as u32,
+            iterations: lbfgs.n_iter as u32,
+            max_linesearch: lbfgs.max_linesearch as u32,
+            eps: lbfgs.eps,
+            ftol: lbfgs.ftol,
+            wolfe: lbfgs.wolfe,
+            min_step: lbfgs.min_step,
+            max_step: lbfgs.max_step,
+            linesearch: match lbfgs.linesearch {
+                ggml_internal::ggml_linesearch_GGML_LINESEARCH_BACKTRACKING_ARMIJO => {
+                    LineSearch::Armijo
+                }
+                ggml_internal::ggml_linesearch_GGML_LINESEARCH_BACKTRACKING_STRONG_WOLFE => {
+                    LineSearch::StrongWolfe
+                }
+                _ => LineSearch::Wolfe,
+            },
+        }
+    }
+}
+
+fn default_params(type_: ggml_internal::ggml_opt_type) -> ggml_internal::ggml_opt_params {
+    unsafe { ggml_internal::ggml_opt_default_params(type_) }
+}
+
+/// How an optimization run ended, mirroring ggml's status codes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OptResult {
+    /// A convergence threshold was reached.
+    Converged,
+    /// The iteration limit was reached first.
+    DidNotConverge,
+    /// The progress callback asked to stop.
+    Stopped,
+    /// ggml had no context to allocate the optimizer's state in.
+    NoContext,
+    InvalidWolfe,
+    Failed,
+    LineSearchFailed,
+    LineSearchMinimumStep,
+    LineSearchMaximumStep,
+    LineSearchMaximumIterations,
+    LineSearchInvalidParameters,
+    /// A status code this version of the bindings does not know.
+    Other(i32),
+}
+
+impl OptResult {
+    fn from_raw(value: ggml_internal::ggml_opt_result) -> Self {
+        match value {
+            ggml_internal::ggml_opt_result_GGML_OPT_OK => OptResult::Converged,
+            ggml_internal::ggml_opt_result_GGML_OPT_DID_NOT_CONVERGE => OptResult::DidNotConverge,
+            ggml_internal::ggml_opt_result_GGML_OPT_NO_CONTEXT => OptResult::NoContext,
+            ggml_internal::ggml_opt_result_GGML_OPT_INVALID_WOLFE => OptResult::InvalidWolfe,
+            ggml_internal::ggml_opt_result_GGML_OPT_FAIL => OptResult::Failed,
+            ggml_internal::ggml_opt_result_GGML_LINESEARCH_FAIL => OptResult::LineSearchFailed,
+            ggml_internal::ggml_opt_result_GGML_LINESEARCH_MINIMUM_STEP => {
+                OptResult::LineSearchMinimumStep
+            }
+            ggml_internal::ggml_opt_result_GGML_LINESEARCH_MAXIMUM_STEP => {
+                OptResult::LineSearchMaximumStep
+            }
+            ggml_internal::ggml_opt_result_GGML_LINESEARCH_MAXIMUM_ITERATIONS => {
+                OptResult::LineSearchMaximumIterations
+            }
+            ggml_internal::ggml_opt_result_GGML_LINESEARCH_INVALID_PARAMETERS => {
+                OptResult::LineSearchInvalidParameters
+            }
+            other => OptResult::Other(other as i32),
+        }
+    }
+}
+
+/// State passed to the progress callback of [`Optimizer::minimize_with`].
+#[derive(Clone, Copy, Debug)]
+pub struct OptProgress {
+    /// Iterations done so far.
+    pub iteration: u32,
+    pub loss: f32,
+}
+
+/// Summary of a finished [`Optimizer`] run.
+#[derive(Clone, Copy, Debug)]
+pub struct OptOutcome {
+    pub result: OptResult,
+    pub iterations: u32,
+    /// The loss at the final parameters.
+    pub loss: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Method {
+    Adam(AdamParams),
+    Lbfgs(LbfgsParams),
+}
+
+/// What ggml allocates in the context to minimize a loss: the graphs
+/// computing it and its gradients, and the optimizer's own tensors.
+struct State {
+    ctx: Weak<RawContext>,
+    loss: *mut ggml_internal::ggml_tensor,
+    forward: ComputationGraph,
+    backward: ComputationGraph,
+    opt: Box<ggml_internal::ggml_opt_context>,
+}
+
+/// Minimizes a scalar loss by updating the tensors marked with
+/// [`Tensor::set_param`] in place, using ggml's built-in optimizers.
+///
+/// The graphs and optimizer state are allocated in the context on the first
+/// run and reused by later runs on the same loss, which continue from where
+/// the previous one stopped, e.g. with Adam's moment estimates. Minimizing
+/// another loss allocates new ones.
+pub struct Optimizer {
+    method: Method,
+    n_threads: i32,
+    state: Option<State>,
+}
+
+impl fmt::Debug for Optimizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Optimizer")
+            .field("method", &self.method)
+            .field("n_threads", &self.n_threads)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Optimizer {
+    pub fn adam(params: AdamParams) -> Self {
+        Self {
+            method: Method::Adam(params),
+            n_threads: 1,
+            state: None,
+        }
+    }
+
+    pub fn lbfgs(params: LbfgsParams) -> Self {
+        Self {
+            method: Method::Lbfgs(params),
+            n_threads: 1,
+            state: None,
+        }
+    }
+
+    pub fn with_threads(self, n_threads: i32) -> Self {
+        Self {
+            n_threads,
+            state: None,
+            ..self
+        }
+    }
+
+    /// Runs the optimizer until it converges or reaches its iteration limit.
+    pub fn minimize(&mut self, ctx: &Context, loss: &Tensor) -> Result<OptOutcome, Error> {
+        self.minimize_with(ctx, loss, u32::MAX, |_| ControlFlow::Continue(()))
+    }
+
+    /// Like [`Optimizer::minimize`], calling `progress` every `report_every`
+    /// iterations. Returning [`ControlFlow::Break`] from it ends the run with
+    /// [`OptResult::Stopped`].
+    ///
+    /// ggml's optimizers run a fixed number of iterations per call, so the
+    /// run is split into calls of `report_every` iterations. Each call starts
+    /// with an extra evaluation of the loss and, for L-BFGS, restarts the
+    /// search direction from the gradient, so small values slow the run down.
+    pub fn minimize_with(
+        &mut self,
+        ctx: &Context,
+        loss: &Tensor,
+        report_every: u32,
+        mut progress: impl FnMut(&OptProgress) -> ControlFlow<()>,
+    ) -> Result<OptOutcome, Error> {
+        const OP: &str = "minimize";
+        ctx.check_operands(OP, &[loss])?;
+        if loss.nelements() != 1 {
+            return Err(Error::InvalidOperands {
+                op: OP,
+                reason: "the loss must have a single element",
+            });
+        }
+
+        let ctx_handle = Arc::downgrade(&ctx.ptr);
+        let reuse = self
+            .state
+            .as_ref()
+            .is_some_and(|s| s.loss == loss.ptr.as_ptr() && s.ctx.ptr_eq(&ctx_handle));
+        if !reuse {
+            self.state = Some(self.init_state(ctx, loss)?);
+        }
+        let State {
+            forward,
+            backward,
+            opt,
+            ..
+        } = self.state.as_mut().expect("The state was just set");
+
+        let iterations = match self.method {
+            Method::Adam(p) => p.iterations,
+            Method::Lbfgs(p) => p.iterations,
+        };
+        // ggml counts iterations across calls in `opt.iter`, including those
+        // of earlier runs.
+        let start = opt.iter.max(0) as u32;
+        let report_every = report_every.max(1);
+        let mut done = 0;
+        loop {
+            let chunk = report_every.min(iterations - done);
+            match self.method {
+                Method::Adam(_) => opt.params.adam.n_iter = chunk as i32,
+                Method::Lbfgs(_) => opt.params.lbfgs.n_iter = chunk as i32,
+            }
+            let result = OptResult::from_raw(unsafe {
+                ggml_internal::ggml_opt_resume_g(
+                    ctx.ptr.as_ptr(),
+                    &mut **opt,
+                    loss.ptr.as_ptr(),
+                    &mut forward.inner,
+                    &mut backward.inner,
+                )
+            });
+            crate::ops::resume_map_op_panic();
+            // A call that did not converge used up its whole chunk.
+            done = match result {
+                OptResult::DidNotConverge => done + chunk,
+                _ => (opt.iter.max(0) as u32)
+                    .saturating_sub(start)
+                    .clamp(done, done + chunk),
+            };
+
+            let state = OptProgress {
+                iteration: done,
+                loss: loss.get_f32_1d(0),
+            };
+            let outcome = |result| OptOutcome {
+                result,
+                iterations: state.iteration,
+                loss: state.loss,
+            };
+            if result != OptResult::DidNotConverge || done >= iterations {
+                return Ok(outcome(result));
+            }
+            if progress(&state).is_break() {
+                return Ok(outcome(OptResult::Stopped));
+            }
+        }
+    }
+
+    /// Builds the graphs of `loss` and allocates the optimizer's state.
+    fn init_state(&self, ctx: &Context, loss: &Tensor) -> Result<State, Error> {
+        let mut forward = ComputationGraph::new(self.n_threads);
+        forward.build_forward_expand(loss);
+        let nx: i64 = forward
+            .nodes()
+            .filter(|t| t.is_param())
+            .map(|t| t.nelements() as i64)
+            .sum();
+        if nx == 0 {
+            return Err(Error::InvalidOperands {
+                op: "minimize",
+                reason: "the loss does not depend on any parameter",
+            });
+        }
+        let backward = ComputationGraph::build_backward(&mut forward, true)?;
+
+        let mut opt = Box::new(unsafe { std::mem::zeroed::<ggml_internal::ggml_opt_context>() });
+        unsafe { ggml_internal::ggml_opt_init(ctx.ptr.as_ptr(), &mut *opt, self.raw_params(), nx) };
+        Ok(State {
+            ctx: Arc::downgrade(&ctx.ptr),
+            loss: loss.ptr.as_ptr(),
+            forward,
+            backward,
+            opt,
+        })
+    }
+
+    fn raw_params(&self) -> ggml_internal::ggml_opt_params {
+        let mut params = match self.method {
+            Method::Adam(p) => {
+                let mut params = default_params(ggml_internal::ggml_opt_type_GGML_OPT_ADAM);
+                params.adam.n_iter = p.iterations as i32;
+                params.adam.alpha = p.learning_rate;
+                params.adam.beta1 = p.beta1;
+                params.adam.beta2 = p.beta2;
+                params.adam.eps = p.eps;
+                params.adam.eps_f = p.eps_f;
+                params.adam.eps_g = p.eps_g;
+                params
+            }
+            Method::Lbfgs(p) => {
+                let mut params = default_params(ggml_internal::ggml_opt_type_GGML_OPT_LBFGS);
+                params.lbfgs.m = p.memory as i32;
+                params.lbfgs.n_iter = p.iterations as i32;
+                params.lbfgs.max_linesearch = p.max_linesearch as i32;
+                params.lbfgs.eps = p.eps;
+                params.lbfgs.ftol = p.ftol;
+                params.lbfgs.wolfe = p.wolfe;
+                params.lbfgs.min_step = p.min_step;
+                params.lbfgs.max_step = p.max_step;
+                params.lbfgs.linesearch = p.linesearch as u32;
+                params
+            }
+        };
+        params.n_threads = self.n_threads;
+        params
+    }
+}
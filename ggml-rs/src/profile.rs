@@ -0,0 +1,221 @@
+use crate::context::Context;
+use crate::graph::ComputationGraph;
+use crate::tensor::{OpKind, Tensor};
+use std::cmp::Reverse;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Timing of one node of a profiled computation.
+#[derive(Clone, Debug)]
+pub struct NodeProfile {
+    /// Position of the node in evaluation order.
+    pub index: usize,
+    pub name: String,
+    pub op: OpKind,
+    pub shape: [i32; 4],
+    pub time: Duration,
+    /// Rough floating point operation count, see [`NodeProfile::gflops`].
+    pub flops: u64,
+    /// Number of threads ggml split the node across.
+    pub n_threads: usize,
+}
+
+impl NodeProfile {
+    /// Achieved throughput. The FLOP counts are estimates (a multiply-add
+    /// counts as two, activations as a handful per element), good for
+    /// comparing ops against each other rather than against peak numbers.
+    pub fn gflops(&self) -> f64 {
+        gflops(self.flops, self.time)
+    }
+}
+
+/// Time spent in all nodes of one op kind.
+#[derive(Clone, Debug)]
+pub struct OpProfile {
+    pub op: OpKind,
+    pub count: usize,
+    pub time: Duration,
+    pub flops: u64,
+}
+
+/// Per-node timings of one [`Context::graph_compute_profiled`] call.
+#[derive(Clone, Debug, Default)]
+pub struct ProfileReport {
+    pub nodes: Vec<NodeProfile>,
+    pub total: Duration,
+}
+
+impl ProfileReport {
+    /// The `n` slowest nodes, slowest first.
+    pub fn slowest(&self, n: usize) -> Vec<&NodeProfile> {
+        let mut nodes: Vec<&NodeProfile> = self.nodes.iter().collect();
+        nodes.sort_by_key(|node| Reverse(node.time));
+        nodes.truncate(n);
+        nodes
+    }
+
+    /// Time per op kind, most expensive first.
+    pub fn by_op(&self) -> Vec<OpProfile> {
+        let mut ops: Vec<OpProfile> = Vec::new();
+        for node in &self.nodes {
+            match ops.iter_mut().find(|p| p.op == node.op) {
+                Some(p) => {
+                    p.count += 1;
+                    p.time += node.time;
+                    p.flops += node.flops;
+                }
+                None => ops.push(OpProfile {
+                    op: node.op,
+                    count: 1,
+                    time: node.time,
+                    flops: node.flops,
+                }),
+            }
+        }
+        ops.sort_by_key(|op| Reverse(op.time));
+        ops
+    }
+
+    fn share(&self, time: Duration) -> f64 {
+        if self.total.is_zero() {
+            0.0
+        } else {
+            100.0 * time.as_secs_f64() / self.total.as_secs_f64()
+        }
+    }
+}
+
+/// Prints the ten slowest nodes followed by the time per op kind.
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flops: u64 = self.nodes.iter().map(|n| n.flops).sum();
+        writeln!(
+            f,
+            "{} nodes in {:.3} ms, {:.2} GFLOP/s",
+            self.nodes.len(),
+            ms(self.total),
+            gflops(flops, self.total)
+        )?;
+
+        writeln!(f, "slowest nodes:")?;
+        for node in self.slowest(10) {
+            writeln!(
+                f,
+                "  #{:<5} {:<14} {:<24} {:>10.3} ms {:>6.1}% {:>8.2} GFLOP/s {:>3} threads",
+                node.index,
+                node.op.name(),
+                node.name,
+                ms(node.time),
+                self.share(node.time),
+                node.gflops(),
+                node.n_threads
+            )?;
+        }
+
+        writeln!(f, "per op:")?;
+        for op in self.by_op() {
+            writeln!(
+                f,
+                "  {:<14} {:>5} nodes {:>10.3} ms {:>6.1}% {:>8.2} GFLOP/s",
+                op.op.name(),
+                op.count,
+                ms(op.time),
+                self.share(op.time),
+                gflops(op.flops, op.time)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1e3
+}
+
+fn gflops(flops: u64, time: Duration) -> f64 {
+    if time.is_zero() {
+        0.0
+    } else {
+        flops as f64 / time.as_secs_f64() / 1e9
+    }
+}
+
+/// Rough floating point operation count of computing `node` once. Ops that
+/// only move data (views, copies, reshapes) count as zero.
+fn estimate_flops(node: &Tensor) -> u64 {
+    let n = node.nelements() as u64;
+    let sources = node.sources();
+    let src_ne = |i: usize| {
+        sources
+            .get(i)
+            .map_or([0; 4], |t| t.get_ne().map(|n| n as u64))
+    };
+
+    match node.op() {
+        // Every output element is a dot product along the shared first axis.
+        OpKind::MulMat => 2 * src_ne(0)[0] * n,
+        OpKind::Conv1dS1Ph | OpKind::Conv1dS2Ph => 2 * src_ne(0)[0] * src_ne(0)[1] * n,
+        OpKind::Conv2dSkP0 => 2 * src_ne(0)[0] * src_ne(0)[1] * src_ne(0)[2] * n,
+        // Q·K and softmax(Q·K)·V, each a dot product per key.
+        OpKind::FlashAttn => 4 * src_ne(1)[1] * n,
+        OpKind::Sum | OpKind::Mean => src_ne(0).iter().product(),
+        OpKind::Add
+        | OpKind::Sub
+        | OpKind::Mul
+        | OpKind::Div
+        | OpKind::Sqr
+        | OpKind::Sqrt
+        | OpKind::Abs
+        | OpKind::Sgn
+        | OpKind::Neg
+        | OpKind::Step
+        | OpKind::Relu
+        | OpKind::Scale
+        | OpKind::DiagMaskInf
+        | OpKind::Alibi
+        | OpKind::MapUnary
+        | OpKind::MapBinary => n,
+        OpKind::Norm | OpKind::RmsNorm | OpKind::SoftMax => 5 * n,
+        OpKind::Rope => 6 * n,
+        OpKind::Gelu | OpKind::Silu => 8 * n,
+        _ => 0,
+    }
+}
+
+impl Context<'_> {
+    /// Computes `graph` like [`Context::graph_compute`], but one node at a
+    /// time, timing each node.
+    ///
+    /// Every node is its own ggml computation, so ggml's worker threads are
+    /// started per node; the report is meant for finding which ops dominate,
+    /// and the total will be somewhat higher than an unprofiled run.
+    pub fn graph_compute_profiled(&self, graph: &mut ComputationGraph) -> ProfileReport {
+        graph.assert_alive();
+        let n_threads = graph.n_threads();
+        let mut step = self.outside_scratch(|| graph.step_graph(self.ptr.raw, n_threads));
+        let mut report = ProfileReport::default();
+
+        for i in 0..graph.n_nodes() {
+            let start = Instant::now();
+            self.outside_scratch(|| graph.compute_node(self.ptr.raw, &mut step, i));
+            let time = start.elapsed();
+
+            let node = graph.node(i).expect("Node index is in range");
+            // SAFETY: ggml records the task count of the node it just ran.
+            let n_tasks = unsafe { (*node.ptr.as_ptr()).n_tasks };
+            report.total += time;
+            report.nodes.push(NodeProfile {
+                index: i,
+                name: node.name(),
+                op: node.op(),
+                shape: node.get_ne(),
+                time,
+                flops: estimate_flops(&node),
+                n_threads: n_tasks.max(1) as usize,
+            });
+        }
+
+        crate::ops::resume_map_op_panic();
+        report
+    }
+}
@@ -0,0 +1,119 @@
+use crate::context::{Context, RawContext};
+use crate::error::Error;
+use crate::tensor::Tensor;
+use std::ptr::NonNull;
+use std::sync::Weak;
+
+/// A context that can no longer be modified, made by [`Context::freeze`].
+/// It can be shared between threads, e.g. to load a model's weights once and
+/// serve it from a pool of workers that each compute in a context of their
+/// own.
+///
+/// [`Context`] and [`Tensor`] themselves are neither `Send` nor `Sync`:
+/// tensor handles can allocate in their context through operators such as
+/// `&a + &b` and write to its data without borrowing the context, so a
+/// context moved to another thread could be used from two threads at once.
+pub struct SharedContext<'buf> {
+    ctx: Context<'buf>,
+}
+
+// SAFETY: Once frozen, neither the context nor its tensors are written to:
+// - `SharedContext` only exposes methods that read the context.
+// - Tensor handles created before freezing panic or return `Error::Frozen`
+//   in every method that writes data or flags, operators refuse to allocate
+//   in the context, and graphs of the context itself cannot be computed.
+// - Other contexts may only use its leaf tensors, which are not parameters
+//   and have no gradient, so computing a graph of another context never
+//   writes to them: the ops that write into an operand, `cpy`, refuse a
+//   frozen destination. Views of them built elsewhere are read-only like
+//   them, so neither the view handles nor `cpy` write through them, and
+//   keep the shared context alive while they can be read.
+// The ggml context is freed by the last strong handle, and tensor methods
+// hold one while they run, so dropping the shared context on one thread
+// cannot free memory another thread is reading. Buffers attached before
+// freezing are kept in the same handle and freed after the context.
+unsafe impl Send for SharedContext<'_> {}
+unsafe impl Sync for SharedContext<'_> {}
+
+impl<'buf> Context<'buf> {
+    /// Makes the context read-only so that it can be shared between threads.
+    ///
+    /// Tensor handles of the context stay usable for reading. Writing to
+    /// them panics (or returns [`Error::Frozen`]), and so does building ops
+    /// in the context. Other contexts can use its leaf tensors, i.e. ones
+    /// that only hold data, as operands: an op on a weight and a tensor of a
+    /// compute context is built in the compute context. Views of them built
+    /// there are read-only as well, and they cannot be the destination of
+    /// [`Context::op_cpy`]. Get a handle for another thread with
+    /// [`SharedContext::share`].
+    pub fn freeze(self) -> SharedContext<'buf> {
+        self.ptr.recorded.lock().unwrap().take();
+        self.ptr.freeze();
+        SharedContext { ctx: self }
+    }
+}
+
+impl SharedContext<'_> {
+    /// A handle to `tensor` that can be sent to other threads. Only leaf
+    /// tensors can be shared, as those are the only ones other contexts can
+    /// use.
+    pub fn share(&self, tensor: &Tensor) -> Result<SharedTensor, Error> {
+        const OP: &str = "share";
+        let ctx = tensor.ctx.upgrade().ok_or(Error::ContextDropped)?;
+        if ctx.raw != self.ctx.ptr.raw {
+            return Err(Error::ContextMismatch { op: OP, operand: 0 });
+        }
+        if !tensor.is_plain_leaf() {
+            return Err(Error::InvalidOperands {
+                op: OP,
+                reason: "only leaf tensors of a frozen context can be used by other contexts",
+            });
+        }
+        Ok(SharedTensor {
+            ptr: tensor.ptr,
+            ctx: Weak::clone(&tensor.ctx),
+        })
+    }
+
+    /// Looks up a tensor by name like [`Context::get_tensor`], returning a
+    /// handle for other threads.
+    pub fn get_tensor(&self, name: &str) -> Result<SharedTensor, Error> {
+        let tensor = self
+            .ctx
+            .get_tensor(name)
+            .ok_or_else(|| Error::UnknownTensor {
+                name: name.to_owned(),
+            })?;
+        self.share(&tensor)
+    }
+
+    pub fn used_mem(&self) -> usize {
+        self.ctx.used_mem()
+    }
+}
+
+/// A tensor of a [`SharedContext`] that can be sent to and shared between
+/// threads. Each thread gets its own [`Tensor`] handle with
+/// [`SharedTensor::tensor`].
+#[derive(Clone, Debug)]
+pub struct SharedTensor {
+    ptr: NonNull<ggml_internal::ggml_tensor>,
+    ctx: Weak<RawContext>,
+}
+
+// SAFETY: The tensor belongs to a frozen context, see `SharedContext`.
+// Liveness is tracked by the weak handle, whose counts are atomic.
+unsafe impl Send for SharedTensor {}
+unsafe impl Sync for SharedTensor {}
+
+impl SharedTensor {
+    /// A handle to the tensor for the calling thread. Like every tensor
+    /// handle, it can only be used while the shared context is alive.
+    pub fn tensor(&self) -> Tensor {
+        let _alive = self
+            .ctx
+            .upgrade()
+            .expect("Using a tensor after the context was dropped");
+        Tensor::from_raw(self.ptr.as_ptr(), Weak::clone(&self.ctx))
+    }
+}
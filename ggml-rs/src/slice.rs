@@ -0,0 +1,133 @@
+use crate::context::Context;
+use crate::error::Error;
+use crate::tensor::{Dimension, Element, Tensor};
+
+impl Context<'_> {
+    /// Creates a tensor of `T`'s [`DataType`](crate::DataType) holding a copy
+    /// of `data`. `shape` lists the length of each axis in ggml's order,
+    /// innermost first, so `[3, 2]` is two rows of three elements; it must
+    /// have between one and four axes, each of at most `i32::MAX` elements,
+    /// and match the number of elements.
+    pub fn tensor_from_slice<T: Element>(
+        &self,
+        data: &[T],
+        shape: &[usize],
+    ) -> Result<Tensor, Error> {
+        const OP: &str = "tensor_from_slice";
+        if shape.is_empty() || shape.len() > 4 {
+            return Err(Error::InvalidOperands {
+                op: OP,
+                reason: "shapes have between one and four axes",
+            });
+        }
+        if shape.iter().any(|&n| n > i32::MAX as usize) {
+            return Err(Error::InvalidOperands {
+                op: OP,
+                reason: "ggml axes hold at most i32::MAX elements",
+            });
+        }
+        let expected: usize = shape.iter().product();
+        if data.len() != expected {
+            return Err(Error::LengthMismatch {
+                expected,
+                found: data.len(),
+            });
+        }
+
+        self.check_room()?;
+        let ne: Vec<i32> = shape.iter().map(|&n| n as i32).collect();
+        let raw = unsafe {
+            ggml_internal::ggml_new_tensor(
+                self.ptr.as_ptr(),
+                T::DATA_TYPE.into(),
+                ne.len() as i32,
+                ne.as_ptr(),
+            )
+        };
+        let dim = match shape.len() {
+            1 => Dimension::D1,
+            2 => Dimension::D2,
+            _ => Dimension::D3,
+        };
+        let mut padded = [1; 4];
+        padded[..shape.len()].copy_from_slice(shape);
+        let tensor = self.new_tensor_raw(raw, dim, padded);
+        tensor.copy_from_slice(data)?;
+        Ok(tensor)
+    }
+}
+
+impl Tensor {
+    /// Overwrites the tensor's data with `data`, which must match its element
+    /// type and hold exactly as many elements. The tensor must be contiguous.
+    pub fn copy_from_slice<T: Element>(&self, data: &[T]) -> Result<(), Error> {
+        let ctx = self.ctx.upgrade().ok_or(Error::ContextDropped)?;
+        if ctx.is_read_only(self.ptr) {
+            return Err(Error::Frozen);
+        }
+        check_layout::<T>(self, data.len())?;
+        if !self.is_contiguous() {
+            return Err(Error::NonContiguous);
+        }
+        // SAFETY: The tensor is contiguous and holds `data.len()` elements of
+        // type `T`, as checked above.
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), self.data() as *mut T, data.len());
+        }
+        Ok(())
+    }
+
+    /// Copies the tensor's elements into a vector in ggml's order, innermost
+    /// axis first. Views such as transposes are read through their strides,
+    /// so the result is laid out like a contiguous copy of the view.
+    pub fn to_vec<T: Element>(&self) -> Result<Vec<T>, Error> {
+        let _alive = self.ctx.upgrade().ok_or(Error::ContextDropped)?;
+        check_layout::<T>(self, self.nelements())?;
+        if self.is_contiguous() {
+            let data = self
+                .read_data::<T>()
+                .expect("The tensor's length was checked above");
+            return Ok(data.to_vec());
+        }
+
+        let ne = self.get_ne().map(|n| n as usize);
+        let nb = self.get_nb();
+        let data = self.data() as *const u8;
+        let mut values = Vec::with_capacity(self.nelements());
+        for i3 in 0..ne[3] {
+            for i2 in 0..ne[2] {
+                for i1 in 0..ne[1] {
+                    for i0 in 0..ne[0] {
+                        let offset = i0 * nb[0] + i1 * nb[1] + i2 * nb[2] + i3 * nb[3];
+                        // SAFETY: The offset of every element lies within the
+                        // data the view points into.
+                        values.push(unsafe { (data.add(offset) as *const T).read_unaligned() });
+                    }
+                }
+            }
+        }
+        Ok(values)
+    }
+}
+
+/// Checks that `tensor` has data and that `len` elements of type `T` are the
+/// right type and number for it.
+pub(crate) fn check_layout<T: Element>(tensor: &Tensor, len: usize) -> Result<(), Error> {
+    if tensor.data().is_null() {
+        return Err(Error::NoData);
+    }
+    let dtype = tensor.get_type();
+    if dtype != T::DATA_TYPE {
+        return Err(Error::TypeMismatch {
+            expected: dtype,
+            found: T::DATA_TYPE,
+        });
+    }
+    if len != tensor.nelements() {
+        return Err(Error::LengthMismatch {
+            expected: tensor.nelements(),
+            found: len,
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,169 @@
+use crate::error::Error;
+use crate::graph::ComputationGraph;
+use crate::tensor::{DataType, Tensor};
+use std::fmt;
+
+/// Summary of a tensor's values, see [`Tensor::stats`].
+///
+/// `min`, `max`, `mean` and `std` only cover the finite values, so a single
+/// NaN does not hide the range of the rest; they are NaN if there are none.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TensorStats {
+    /// Number of elements, including non-finite ones.
+    pub count: usize,
+    pub min: f32,
+    pub max: f32,
+    pub mean: f64,
+    /// Population standard deviation.
+    pub std: f64,
+    pub nan_count: usize,
+    /// Number of positive or negative infinities.
+    pub inf_count: usize,
+}
+
+impl TensorStats {
+    pub fn is_finite(&self) -> bool {
+        self.nan_count == 0 && self.inf_count == 0
+    }
+}
+
+impl fmt::Display for TensorStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "min {} max {} mean {} std {} ({} elements, {} NaN, {} inf)",
+            self.min, self.max, self.mean, self.std, self.count, self.nan_count, self.inf_count
+        )
+    }
+}
+
+impl Tensor {
+    /// Computes the range, mean and standard deviation of the tensor's
+    /// values and counts the NaNs and infinities among them, e.g. to find
+    /// where a model starts producing garbage. Quantized tensors are
+    /// dequantized row by row; views are read through their strides.
+    ///
+    /// # Panics
+    /// If the tensor has no data, e.g. in a [`crate::ContextBuilder::no_alloc`]
+    /// context.
+    pub fn stats(&self) -> TensorStats {
+        assert!(!self.data().is_null(), "Reading a tensor without data");
+        let mut stats = TensorStats {
+            count: 0,
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            mean: 0.0,
+            std: 0.0,
+            nan_count: 0,
+            inf_count: 0,
+        };
+        // Welford's algorithm, which stays accurate for large tensors.
+        let (mut finite, mut m2) = (0usize, 0.0f64);
+        self.for_each_row(|row| {
+            stats.count += row.len();
+            for &value in row {
+                if value.is_nan() {
+                    stats.nan_count += 1;
+                } else if value.is_infinite() {
+                    stats.inf_count += 1;
+                } else {
+                    finite += 1;
+                    stats.min = stats.min.min(value);
+                    stats.max = stats.max.max(value);
+                    let delta = value as f64 - stats.mean;
+                    stats.mean += delta / finite as f64;
+                    m2 += delta * (value as f64 - stats.mean);
+                }
+            }
+        });
+        if finite == 0 {
+            stats.min = f32::NAN;
+            stats.max = f32::NAN;
+            stats.mean = f64::NAN;
+            stats.std = f64::NAN;
+        } else {
+            stats.std = (m2 / finite as f64).sqrt();
+        }
+        stats
+    }
+
+    /// Calls `f` with the values of every row, i.e. every run along the
+    /// first axis, converted to `f32`.
+    pub(crate) fn for_each_row(&self, mut f: impl FnMut(&[f32])) {
+        let ne = self.get_ne().map(|n| n as usize);
+        let nb = self.get_nb();
+        let mut row = vec![0.0; ne[0]];
+        for i3 in 0..ne[3] {
+            for i2 in 0..ne[2] {
+                for i1 in 0..ne[1] {
+                    self.read_row_f32(i1 * nb[1] + i2 * nb[2] + i3 * nb[3], &mut row);
+                    f(&row);
+                }
+            }
+        }
+    }
+
+    /// Reads the row starting `offset` bytes into the data into `row`, which
+    /// holds as many values as the first axis.
+    pub(crate) fn read_row_f32(&self, offset: usize, row: &mut [f32]) {
+        let dtype = self.get_type();
+        let stride = self.get_nb()[0];
+        let data = self.data() as *const u8;
+        // SAFETY: `offset` is the start of a row within the tensor, and every
+        // element of the row lies `stride` bytes after the previous one; the
+        // blocks of a quantized row are contiguous.
+        unsafe {
+            let start = data.add(offset);
+            if dtype.is_quantized() {
+                let dequantize = ggml_internal::ggml_internal_get_quantize_fn(dtype as usize)
+                    .dequantize_row_q
+                    .expect("ggml dequantizes every quantized type");
+                dequantize(start as *const _, row.as_mut_ptr(), row.len() as i32);
+                return;
+            }
+            for (i, value) in row.iter_mut().enumerate() {
+                let ptr = start.add(i * stride);
+                *value = match dtype {
+                    DataType::I8 => *(ptr as *const i8) as f32,
+                    DataType::I16 => (ptr as *const i16).read_unaligned() as f32,
+                    DataType::I32 => (ptr as *const i32).read_unaligned() as f32,
+                    DataType::F16 => {
+                        ggml_internal::ggml_fp16_to_fp32((ptr as *const u16).read_unaligned())
+                    }
+                    DataType::F32 => (ptr as *const f32).read_unaligned(),
+                    _ => f32::NAN,
+                };
+            }
+        }
+    }
+}
+
+impl ComputationGraph {
+    /// Checks the nodes of a computed graph in order and returns
+    /// [`Error::NonFinite`] for the first one holding a NaN or an infinity,
+    /// which is usually where a numerical problem starts. Nodes of integer
+    /// types and nodes without data are skipped.
+    ///
+    /// Every element of every node is read, so this is meant for debugging
+    /// rather than for every evaluation.
+    pub fn check_finite(&self) -> Result<(), Error> {
+        for (index, node) in self.nodes().enumerate() {
+            let dtype = node.get_type();
+            let is_float = matches!(dtype, DataType::F16 | DataType::F32) || dtype.is_quantized();
+            if !is_float || node.data().is_null() {
+                continue;
+            }
+            let stats = node.stats();
+            if !stats.is_finite() {
+                return Err(Error::NonFinite {
+                    node: index,
+                    name: node.name(),
+                    op: node.op(),
+                    nan_count: stats.nan_count,
+                    inf_count: stats.inf_count,
+                });
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,12 @@
-use std::{ffi::c_void, ptr::NonNull, sync::Weak};
+use crate::context::{owner_of, Context, RawContext};
+use crate::error::Error;
+use std::{
+    ffi::{c_void, CStr, CString},
+    ptr::NonNull,
+    sync::Weak,
+};
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u32)]
 pub enum DataType {
     I8 = ggml_internal::ggml_type_GGML_TYPE_I8,
@@ -7,9 +14,25 @@ pub enum DataType {
     I32 = ggml_internal::ggml_type_GGML_TYPE_I32,
     F16 = ggml_internal::ggml_type_GGML_TYPE_F16,
     F32 = ggml_internal::ggml_type_GGML_TYPE_F32,
+    Q4_0 = ggml_internal::ggml_type_GGML_TYPE_Q4_0,
+    Q4_1 = ggml_internal::ggml_type_GGML_TYPE_Q4_1,
+    Q5_0 = ggml_internal::ggml_type_GGML_TYPE_Q5_0,
+    Q5_1 = ggml_internal::ggml_type_GGML_TYPE_Q5_1,
+    Q8_0 = ggml_internal::ggml_type_GGML_TYPE_Q8_0,
     COUNT = ggml_internal::ggml_type_GGML_TYPE_COUNT,
 }
 
+impl DataType {
+    /// Whether elements are stored in blocks of quantized values sharing a
+    /// scale, which have to be dequantized to be read.
+    pub fn is_quantized(self) -> bool {
+        matches!(
+            self,
+            DataType::Q4_0 | DataType::Q4_1 | DataType::Q5_0 | DataType::Q5_1 | DataType::Q8_0
+        )
+    }
+}
+
 impl Into<u32> for DataType {
     fn into(self) -> u32 {
         self as u32
@@ -24,12 +47,144 @@ impl From<u32> for DataType {
             ggml_internal::ggml_type_GGML_TYPE_I32 => DataType::I32,
             ggml_internal::ggml_type_GGML_TYPE_F16 => DataType::F16,
             ggml_internal::ggml_type_GGML_TYPE_F32 => DataType::F32,
+            ggml_internal::ggml_type_GGML_TYPE_Q4_0 => DataType::Q4_0,
+            ggml_internal::ggml_type_GGML_TYPE_Q4_1 => DataType::Q4_1,
+            ggml_internal::ggml_type_GGML_TYPE_Q5_0 => DataType::Q5_0,
+            ggml_internal::ggml_type_GGML_TYPE_Q5_1 => DataType::Q5_1,
+            ggml_internal::ggml_type_GGML_TYPE_Q8_0 => DataType::Q8_0,
             ggml_internal::ggml_type_GGML_TYPE_COUNT => DataType::COUNT,
             _ => panic!("Invalid ggml type value {}.", value),
         }
     }
 }
 
+mod private {
+    pub trait Sealed {}
+}
+
+/// A Rust type that matches the in-memory layout of a ggml [`DataType`], so
+/// tensor data of that type can be read and written as a slice of it.
+pub trait Element: private::Sealed + Copy + Send + 'static {
+    const DATA_TYPE: DataType;
+}
+
+macro_rules! impl_element {
+    ($($ty:ty => $dtype:ident),*) => {
+        $(
+            impl private::Sealed for $ty {}
+            impl Element for $ty {
+                const DATA_TYPE: DataType = DataType::$dtype;
+            }
+        )*
+    };
+}
+
+impl_element!(i8 => I8, i16 => I16, i32 => I32, f32 => F32);
+
+/// Declares [`OpKind`] with a variant per ggml op of the pinned revision.
+macro_rules! op_kinds {
+    ($($kind:ident = $op:ident, $name:literal;)*) => {
+        /// The operation that produces a tensor. Tensors created directly
+        /// rather than computed by an op (inputs, weights, constants) have
+        /// [`OpKind::None`].
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub enum OpKind {
+            $($kind,)*
+            /// An op value this crate does not know, e.g. read from a graph
+            /// file written by a different ggml revision.
+            Other(u32),
+        }
+
+        impl From<u32> for OpKind {
+            fn from(value: u32) -> Self {
+                match value {
+                    $(ggml_internal::$op => OpKind::$kind,)*
+                    other => OpKind::Other(other),
+                }
+            }
+        }
+
+        impl OpKind {
+            /// The op's name as ggml spells it, e.g. `mul_mat`.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(OpKind::$kind => $name,)*
+                    OpKind::Other(_) => "other",
+                }
+            }
+        }
+    };
+}
+
+op_kinds! {
+    None = ggml_op_GGML_OP_NONE, "none";
+    Dup = ggml_op_GGML_OP_DUP, "dup";
+    Add = ggml_op_GGML_OP_ADD, "add";
+    Add1 = ggml_op_GGML_OP_ADD1, "add1";
+    Acc = ggml_op_GGML_OP_ACC, "acc";
+    Sub = ggml_op_GGML_OP_SUB, "sub";
+    Mul = ggml_op_GGML_OP_MUL, "mul";
+    Div = ggml_op_GGML_OP_DIV, "div";
+    Sqr = ggml_op_GGML_OP_SQR, "sqr";
+    Sqrt = ggml_op_GGML_OP_SQRT, "sqrt";
+    Log = ggml_op_GGML_OP_LOG, "log";
+    Sum = ggml_op_GGML_OP_SUM, "sum";
+    SumRows = ggml_op_GGML_OP_SUM_ROWS, "sum_rows";
+    Mean = ggml_op_GGML_OP_MEAN, "mean";
+    Repeat = ggml_op_GGML_OP_REPEAT, "repeat";
+    RepeatBack = ggml_op_GGML_OP_REPEAT_BACK, "repeat_back";
+    Abs = ggml_op_GGML_OP_ABS, "abs";
+    Sgn = ggml_op_GGML_OP_SGN, "sgn";
+    Neg = ggml_op_GGML_OP_NEG, "neg";
+    Step = ggml_op_GGML_OP_STEP, "step";
+    Tanh = ggml_op_GGML_OP_TANH, "tanh";
+    Elu = ggml_op_GGML_OP_ELU, "elu";
+    Relu = ggml_op_GGML_OP_RELU, "relu";
+    Gelu = ggml_op_GGML_OP_GELU, "gelu";
+    GeluQuick = ggml_op_GGML_OP_GELU_QUICK, "gelu_quick";
+    Silu = ggml_op_GGML_OP_SILU, "silu";
+    SiluBack = ggml_op_GGML_OP_SILU_BACK, "silu_back";
+    Norm = ggml_op_GGML_OP_NORM, "norm";
+    RmsNorm = ggml_op_GGML_OP_RMS_NORM, "rms_norm";
+    RmsNormBack = ggml_op_GGML_OP_RMS_NORM_BACK, "rms_norm_back";
+    MulMat = ggml_op_GGML_OP_MUL_MAT, "mul_mat";
+    OutProd = ggml_op_GGML_OP_OUT_PROD, "out_prod";
+    Scale = ggml_op_GGML_OP_SCALE, "scale";
+    Set = ggml_op_GGML_OP_SET, "set";
+    Cpy = ggml_op_GGML_OP_CPY, "cpy";
+    Cont = ggml_op_GGML_OP_CONT, "cont";
+    Reshape = ggml_op_GGML_OP_RESHAPE, "reshape";
+    View = ggml_op_GGML_OP_VIEW, "view";
+    Permute = ggml_op_GGML_OP_PERMUTE, "permute";
+    Transpose = ggml_op_GGML_OP_TRANSPOSE, "transpose";
+    GetRows = ggml_op_GGML_OP_GET_ROWS, "get_rows";
+    GetRowsBack = ggml_op_GGML_OP_GET_ROWS_BACK, "get_rows_back";
+    Diag = ggml_op_GGML_OP_DIAG, "diag";
+    DiagMaskInf = ggml_op_GGML_OP_DIAG_MASK_INF, "diag_mask_inf";
+    DiagMaskZero = ggml_op_GGML_OP_DIAG_MASK_ZERO, "diag_mask_zero";
+    SoftMax = ggml_op_GGML_OP_SOFT_MAX, "soft_max";
+    SoftMaxBack = ggml_op_GGML_OP_SOFT_MAX_BACK, "soft_max_back";
+    Rope = ggml_op_GGML_OP_ROPE, "rope";
+    RopeBack = ggml_op_GGML_OP_ROPE_BACK, "rope_back";
+    Alibi = ggml_op_GGML_OP_ALIBI, "alibi";
+    Clamp = ggml_op_GGML_OP_CLAMP, "clamp";
+    Conv1dS1Ph = ggml_op_GGML_OP_CONV_1D_S1_PH, "conv_1d_s1_ph";
+    Conv1dS2Ph = ggml_op_GGML_OP_CONV_1D_S2_PH, "conv_1d_s2_ph";
+    Conv2dSkP0 = ggml_op_GGML_OP_CONV_2D_SK_P0, "conv_2d_sk_p0";
+    FlashAttn = ggml_op_GGML_OP_FLASH_ATTN, "flash_attn";
+    FlashFf = ggml_op_GGML_OP_FLASH_FF, "flash_ff";
+    FlashAttnBack = ggml_op_GGML_OP_FLASH_ATTN_BACK, "flash_attn_back";
+    WinPart = ggml_op_GGML_OP_WIN_PART, "win_part";
+    WinUnpart = ggml_op_GGML_OP_WIN_UNPART, "win_unpart";
+    MapUnary = ggml_op_GGML_OP_MAP_UNARY, "map_unary";
+    MapBinary = ggml_op_GGML_OP_MAP_BINARY, "map_binary";
+    MapCustom1 = ggml_op_GGML_OP_MAP_CUSTOM1, "map_custom1";
+    MapCustom2 = ggml_op_GGML_OP_MAP_CUSTOM2, "map_custom2";
+    MapCustom3 = ggml_op_GGML_OP_MAP_CUSTOM3, "map_custom3";
+    CrossEntropyLoss = ggml_op_GGML_OP_CROSS_ENTROPY_LOSS, "cross_entropy_loss";
+    CrossEntropyLossBack = ggml_op_GGML_OP_CROSS_ENTROPY_LOSS_BACK, "cross_entropy_loss_back";
+}
+
 #[derive(Clone)]
 pub enum Dimension {
     Scalar,
@@ -42,12 +197,16 @@ pub enum Dimension {
 /// underlying context it was created with is alive.
 pub struct Tensor {
     pub(crate) ptr: NonNull<ggml_internal::ggml_tensor>,
-    pub(crate) ctx: Weak<NonNull<ggml_internal::ggml_context>>,
+    pub(crate) ctx: Weak<RawContext>,
     pub(crate) dim: Dimension,
     pub(crate) shape: [usize; 4],
 }
 
 impl Tensor {
+    /// The longest name in bytes [`Tensor::set_name`] accepts; ggml keeps
+    /// names nul-terminated in a buffer of `GGML_MAX_NAME` bytes.
+    pub const MAX_NAME_LEN: usize = ggml_internal::GGML_MAX_NAME as usize - 1;
+
     /// Creates a shared copy of this tensor pointer.
     pub fn share(&self) -> Self {
         Tensor {
@@ -58,6 +217,25 @@ impl Tensor {
         }
     }
 
+    /// Wraps a tensor created by ggml, reading its dimensionality and shape
+    /// back from the tensor itself.
+    pub(crate) fn from_raw(raw: *mut ggml_internal::ggml_tensor, ctx: Weak<RawContext>) -> Self {
+        let ptr = NonNull::new(raw).expect("Should not be null");
+        // SAFETY: ggml just handed out this tensor, so it is initialized.
+        let (n_dims, ne) = unsafe { ((*raw).n_dims, (*raw).ne) };
+        let dim = match n_dims {
+            1 => Dimension::D1,
+            2 => Dimension::D2,
+            _ => Dimension::D3,
+        };
+        Tensor {
+            ptr,
+            ctx,
+            dim,
+            shape: ne.map(|n| n as usize),
+        }
+    }
+
     pub fn raw_weak(&self) -> Weak<NonNull<ggml_internal::ggml_tensor>> {
         self.with_alive_ctx(|| {
             // SAFETY: The with_alive_cazll guarantees the context is alive
@@ -73,6 +251,29 @@ impl Tensor {
         }
     }
 
+    /// Like `with_alive_ctx`, for methods that modify the tensor's data.
+    /// Views of frozen tensors are refused like the frozen tensors.
+    fn with_writable_ctx<U>(&self, f: impl Fn() -> U) -> U {
+        match self.ctx.upgrade() {
+            Some(ctx) if ctx.is_read_only(self.ptr) => {
+                panic!("Cannot modify a tensor of a frozen context")
+            }
+            Some(_ctx) => f(),
+            None => panic!("Using a tensor after the context was dropped"),
+        }
+    }
+
+    /// Whether the tensor only holds data: it is not computed by an op, not
+    /// a parameter and has no gradient. Graphs never write to such tensors,
+    /// which is what lets contexts share the ones of a frozen context.
+    pub(crate) fn is_plain_leaf(&self) -> bool {
+        self.with_alive_ctx(|| {
+            // SAFETY: The with_alive_call guarantees the context is alive
+            let raw = unsafe { &*self.ptr.as_ptr() };
+            raw.op == ggml_internal::ggml_op_GGML_OP_NONE && !raw.is_param && raw.grad.is_null()
+        })
+    }
+
     pub fn nbytes(&self) -> usize {
         self.with_alive_ctx(|| {
             // SAFETY: The with_alive_call guarantees the context is alive
@@ -95,21 +296,21 @@ impl Tensor {
     }
 
     pub fn set_i32<T: Into<i32> + Copy>(&self, value: T) {
-        self.with_alive_ctx(|| {
+        self.with_writable_ctx(|| {
             // SAFETY: The with_alive_call guarantees the context is alive
             unsafe { ggml_internal::ggml_set_i32(self.ptr.as_ptr(), value.into()) }
         });
     }
 
     pub fn set_f32<T: Into<f32> + Copy>(&self, value: T) {
-        self.with_alive_ctx(|| {
+        self.with_writable_ctx(|| {
             // SAFETY: The with_alive_call guarantees the context is alive
             unsafe { ggml_internal::ggml_set_f32(self.ptr.as_ptr(), value.into()) }
         });
     }
 
     pub fn set_i32_1d<T: Into<i32> + Copy>(&self, idx: usize, value: T) -> Result<(), ()> {
-        self.with_alive_ctx(|| {
+        self.with_writable_ctx(|| {
             if self.nelements() <= idx {
                 Err(())
             } else {
@@ -123,7 +324,7 @@ impl Tensor {
     }
 
     pub fn set_f32_1d<T: Into<f32> + Copy>(&self, idx: usize, value: T) -> Result<(), ()> {
-        self.with_alive_ctx(|| {
+        self.with_writable_ctx(|| {
             if self.nelements() <= idx {
                 Err(())
             } else {
@@ -150,6 +351,12 @@ impl Tensor {
         })
     }
 
+    /// The number of axes the tensor has. [`Tensor::get_ne`] always returns
+    /// four, padded with ones.
+    pub fn n_dims(&self) -> usize {
+        self.with_alive_ctx(|| unsafe { (*self.ptr.as_ptr()).n_dims as usize })
+    }
+
     pub fn get_ne(&self) -> [i32; 4] {
         self.with_alive_ctx(|| unsafe { *self.ptr.as_ptr() }.ne)
     }
@@ -158,6 +365,93 @@ impl Tensor {
         self.with_alive_ctx(|| unsafe { *self.ptr.as_ptr() }.nb)
     }
 
+    /// The name ggml stores alongside the tensor; empty unless one was set
+    /// with [`Tensor::set_name`].
+    pub fn name(&self) -> String {
+        self.with_alive_ctx(|| {
+            // SAFETY: The with_alive_call guarantees the context is alive, and
+            // ggml keeps the name nul-terminated.
+            unsafe { CStr::from_ptr((*self.ptr.as_ptr()).name.as_ptr()) }
+                .to_string_lossy()
+                .into_owned()
+        })
+    }
+
+    /// Names the tensor, e.g. after the model field it holds. Names show up
+    /// in debug output and graph dumps, and tensors can be looked up by name
+    /// with [`Context::get_tensor`] and [`crate::ComputationGraph::get_tensor`].
+    ///
+    /// ggml stores names in a fixed buffer, so a name longer than
+    /// [`Tensor::MAX_NAME_LEN`] bytes or containing a nul character is
+    /// rejected with [`Error::InvalidName`] rather than cut off.
+    pub fn set_name(&self, name: &str) -> Result<(), Error> {
+        let ctx = self.ctx.upgrade().ok_or(Error::ContextDropped)?;
+        if ctx.is_frozen() {
+            return Err(Error::Frozen);
+        }
+        let c_name = CString::new(name)
+            .ok()
+            .filter(|_| name.len() <= Self::MAX_NAME_LEN)
+            .ok_or_else(|| Error::InvalidName {
+                name: name.to_owned(),
+            })?;
+        unsafe { ggml_internal::ggml_set_name(self.ptr.as_ptr(), c_name.as_ptr()) };
+        Ok(())
+    }
+
+    pub fn op(&self) -> OpKind {
+        self.with_alive_ctx(|| unsafe { (*self.ptr.as_ptr()).op.into() })
+    }
+
+    /// The tensors this one is computed from, in ggml's operand order. Empty
+    /// for tensors that are not the result of an op.
+    pub fn sources(&self) -> Vec<Tensor> {
+        self.with_alive_ctx(|| {
+            // SAFETY: The with_alive_call guarantees the context is alive
+            let raw = unsafe { *self.ptr.as_ptr() };
+            [raw.src0, raw.src1]
+                .into_iter()
+                .chain(raw.opt)
+                .filter_map(NonNull::new)
+                .map(|src| {
+                    let owner = owner_of(src).unwrap_or_else(|| Weak::clone(&self.ctx));
+                    Tensor::from_raw(src.as_ptr(), owner)
+                })
+                .collect()
+        })
+    }
+
+    /// Whether the tensor has been marked as a trainable parameter.
+    pub fn is_param(&self) -> bool {
+        self.with_alive_ctx(|| unsafe { (*self.ptr.as_ptr()).is_param })
+    }
+
+    /// Marks the tensor as a trainable parameter, so that
+    /// [`crate::ComputationGraph::build_backward`] computes its gradient.
+    /// Must be called before the graph is built.
+    pub fn set_param(&self) {
+        let ctx = self
+            .ctx
+            .upgrade()
+            .expect("Using a tensor after the context was dropped");
+        assert!(
+            !ctx.is_read_only(self.ptr),
+            "Cannot modify a tensor of a frozen context"
+        );
+        unsafe { ggml_internal::ggml_set_param(ctx.as_ptr(), self.ptr.as_ptr()) }
+    }
+
+    /// The gradient of the tensor, filled in when a backward graph is
+    /// computed. `None` unless the tensor is a parameter or depends on one.
+    pub fn grad(&self) -> Option<Tensor> {
+        self.with_alive_ctx(|| {
+            // SAFETY: The with_alive_call guarantees the context is alive
+            let grad = NonNull::new(unsafe { (*self.ptr.as_ptr()).grad })?;
+            let owner = owner_of(grad).unwrap_or_else(|| Weak::clone(&self.ctx));
+            Some(Tensor::from_raw(grad.as_ptr(), owner))
+        })
+    }
+
     pub fn get_type(&self) -> DataType {
         self.with_alive_ctx(|| unsafe { (*self.ptr.as_ptr()).type_.into() })
     }
@@ -166,6 +460,84 @@ impl Tensor {
         self.with_alive_ctx(|| unsafe { ggml_internal::ggml_element_size(self.ptr.as_ptr()) })
     }
 
+    /// Whether the elements are packed in memory in axis order, without gaps.
+    /// Views such as permutations and transposes usually are not.
+    pub fn is_contiguous(&self) -> bool {
+        self.with_alive_ctx(|| {
+            let (type_, ne, nb) = {
+                // SAFETY: The with_alive_call guarantees the context is alive
+                let raw = unsafe { &*self.ptr.as_ptr() };
+                (raw.type_, raw.ne.map(|n| n as usize), raw.nb)
+            };
+            let (type_size, blck_size) = unsafe {
+                (
+                    ggml_internal::ggml_type_size(type_),
+                    ggml_internal::ggml_blck_size(type_) as usize,
+                )
+            };
+            nb[0] == type_size
+                && nb[1] == nb[0] * ne[0] / blck_size
+                && nb[2] == nb[1] * ne[1]
+                && nb[3] == nb[2] * ne[2]
+        })
+    }
+
+    /// Points the tensor at `data` instead of its current element data, if
+    /// any. `data` must hold at least [`Tensor::nbytes`] bytes and be aligned
+    /// to the element size.
+    ///
+    /// # Safety
+    /// `data` must stay valid, and must not be accessed other than through
+    /// this tensor, for as long as the tensor or any graph using it is used.
+    /// [`Context::attach_data`] is the safe alternative for owned buffers.
+    pub unsafe fn set_data_from(&self, data: &mut [u8]) -> Result<(), Error> {
+        let ctx = self.ctx.upgrade().ok_or(Error::ContextDropped)?;
+        if ctx.is_frozen() {
+            return Err(Error::Frozen);
+        }
+        let needed = self.nbytes();
+        if data.len() < needed {
+            return Err(Error::BufferTooSmall {
+                needed,
+                found: data.len(),
+            });
+        }
+        let align = self.element_size();
+        if align.is_power_of_two() && data.as_ptr().align_offset(align) != 0 {
+            return Err(Error::Misaligned { required: align });
+        }
+        (*self.ptr.as_ptr()).data = data.as_mut_ptr() as *mut c_void;
+        Ok(())
+    }
+
+    /// Copies this tensor's type, shape and data into a new tensor owned by
+    /// `ctx`. Ops refuse operands created in another context, so this is the
+    /// way to move data between contexts deliberately.
+    pub fn copy_to(&self, ctx: &Context) -> Result<Tensor, Error> {
+        let _alive = self.ctx.upgrade().ok_or(Error::ContextDropped)?;
+        if !self.is_contiguous() {
+            return Err(Error::NonContiguous);
+        }
+        ctx.check_room()?;
+
+        // SAFETY: `_alive` keeps the source context alive for this call.
+        let src = unsafe { *self.ptr.as_ptr() };
+        let raw = unsafe {
+            ggml_internal::ggml_new_tensor(ctx.ptr.as_ptr(), src.type_, src.n_dims, src.ne.as_ptr())
+        };
+        let copy = ctx.new_tensor_raw(raw, self.dim.clone(), self.shape);
+        // SAFETY: Both tensors have the same type and shape and the source is
+        // contiguous, so both hold exactly `nbytes` bytes.
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                src.data as *const u8,
+                copy.data() as *mut u8,
+                self.nbytes(),
+            );
+        }
+        Ok(copy)
+    }
+
     /// # Safety
     /// Caller should ensure bounds are checked or use `set_*` functions    
     unsafe fn write_data_raw(&self, src: &[u8]) {
@@ -195,14 +567,16 @@ impl Tensor {
     }
 
     pub fn write_bytes(&self, src: &[u8]) -> Result<(), ()> {
-        if self.nbytes() < src.len() {
-            Err(())
-        } else {
-            unsafe {
-                self.write_data_raw(src);
+        self.with_writable_ctx(|| {
+            if self.nbytes() < src.len() {
+                Err(())
+            } else {
+                unsafe {
+                    self.write_data_raw(src);
+                }
+                Ok(())
             }
-            Ok(())
-        }
+        })
     }
 }
 
@@ -216,8 +590,12 @@ impl std::fmt::Debug for Tensor {
             DataType::I32 => debug_struct.field("data", &self.read_data::<i32>()),
             DataType::F16 => debug_struct.field("data", &self.read_data::<f32>()), // warning, need f16
             DataType::F32 => debug_struct.field("data", &self.read_data::<f32>()),
+            DataType::Q4_0 | DataType::Q4_1 | DataType::Q5_0 | DataType::Q5_1 | DataType::Q8_0 => {
+                debug_struct.field("data", &self.read_elements::<u8>(0, self.nbytes()))
+            }
             DataType::COUNT => debug_struct.field("data", &self.read_data::<usize>()),
         }
+        .field("name", &self.name())
         .field("ptr", &self.ptr)
         .field("ctx", &self.ctx)
         .finish()
@@ -0,0 +1,127 @@
+//! The size of the work buffer ggml needs to compute a graph, mirroring the
+//! task planning at the start of `ggml_graph_compute` in the pinned ggml
+//! revision (see `ggml-internal/build.rs`).
+//!
+//! ggml only works the size out while it computes, and allocates the buffer
+//! in the context unless the graph already has one that is large enough.
+//! Knowing the size up front lets the bindings allocate a buffer once and
+//! share it between computations, and report it in memory plans. The sizes
+//! may only ever err on the large side: ggml aborts when it is handed a
+//! buffer smaller than it needs.
+
+use crate::tensor::OpKind;
+use ggml_internal::ggml_tensor;
+
+/// `CACHE_LINE_SIZE` in ggml.c; ggml pads the buffer by one cache line per
+/// extra thread.
+const CACHE_LINE_SIZE: usize = 64;
+
+/// `GGML_SOFT_MAX_UNROLL` in ggml.c.
+const SOFT_MAX_UNROLL: i64 = 4;
+
+const F16_SIZE: usize = 2;
+const F32_SIZE: usize = 4;
+
+/// Bytes of work buffer ggml needs to compute `nodes` with `n_threads`
+/// threads, including the per-thread padding. Zero if none of the nodes
+/// needs any, in which case ggml does not allocate a buffer.
+///
+/// # Safety
+/// Every node and its sources must be alive.
+pub(crate) unsafe fn graph_work_size(nodes: &[*mut ggml_tensor], n_threads: usize) -> usize {
+    let work_size = nodes
+        .iter()
+        .map(|&node| node_work_size(&*node, n_threads))
+        .max()
+        .unwrap_or(0);
+    padded_work_size(work_size, n_threads)
+}
+
+/// The buffer ggml allocates for a largest per-node need of `work_size`.
+pub(crate) fn padded_work_size(work_size: usize, n_threads: usize) -> usize {
+    if work_size == 0 {
+        0
+    } else {
+        work_size + CACHE_LINE_SIZE * (n_threads.max(1) - 1)
+    }
+}
+
+/// The per-node need `ggml_graph_compute` checks a buffer of
+/// `buffer_size` bytes against when computing with `n_threads` threads.
+pub(crate) fn usable_work_size(buffer_size: usize, n_threads: usize) -> usize {
+    buffer_size.saturating_sub(CACHE_LINE_SIZE * (n_threads.max(1) - 1))
+}
+
+/// Bytes of work buffer `node` needs, before padding.
+///
+/// # Safety
+/// The node's sources must be alive.
+unsafe fn node_work_size(node: &ggml_tensor, n_threads: usize) -> usize {
+    let src0 = node.src0.as_ref();
+    let src1 = node.src1.as_ref();
+    let quantized = |t: &ggml_tensor| ggml_internal::ggml_is_quantized(t.type_);
+    let ne = |t: Option<&ggml_tensor>, i: usize| t.map_or(0, |t| t.ne[i] as usize);
+
+    match OpKind::from(node.op) {
+        OpKind::Cpy | OpKind::Dup if quantized(node) => F32_SIZE * ne(Some(node), 0) * n_threads,
+        OpKind::Add | OpKind::Add1 if src0.is_some_and(quantized) => {
+            F32_SIZE * ne(src0, 0) * n_threads
+        }
+        OpKind::Acc if src0.is_some_and(quantized) => F32_SIZE * ne(src1, 0) * n_threads,
+        // ggml converts `src1` to the type `src0` is multiplied with, at
+        // most two bytes per element for every type but F32, which needs no
+        // conversion. The bindings build ggml without BLAS, whose path
+        // sizes the buffer differently.
+        OpKind::MulMat | OpKind::OutProd => match (src0, src1) {
+            (Some(src0), Some(src1)) if src0.type_ != ggml_internal::ggml_type_GGML_TYPE_F32 => {
+                if src1.type_ == src0.type_ {
+                    0
+                } else {
+                    F16_SIZE * src1.ne.iter().map(|&n| n as usize).product::<usize>()
+                }
+            }
+            _ => 0,
+        },
+        OpKind::Conv1dS1Ph | OpKind::Conv1dS2Ph => {
+            let nk = ne(src0, 0);
+            let elements =
+                nk * up32(ne(src0, 1)) * ne(src0, 2) + (2 * (nk / 2) + ne(src1, 0)) * ne(src1, 1);
+            elements * conv_element_size(src0)
+        }
+        OpKind::Conv2dSkP0 => ne(src1, 0) * ne(src1, 1) * ne(src1, 2) * conv_element_size(src0),
+        OpKind::FlashAttn => {
+            let ne11 = up(ne(src1, 1) as i64, SOFT_MAX_UNROLL) as usize;
+            2 * F32_SIZE * ne11 * n_threads
+        }
+        OpKind::FlashFf => 2 * F32_SIZE * ne(src1, 1) * n_threads,
+        OpKind::FlashAttnBack => {
+            let d = ne(src0, 0);
+            let ne11 = up(ne(src1, 1) as i64, SOFT_MAX_UNROLL) as usize;
+            2 * F32_SIZE * d.max(ne11) * 2 * n_threads
+        }
+        OpKind::CrossEntropyLoss => {
+            let type_size = ggml_internal::ggml_type_size(node.type_);
+            type_size * (n_threads + ne(src0, 0) * n_threads)
+        }
+        OpKind::CrossEntropyLossBack => {
+            ggml_internal::ggml_type_size(node.type_) * ne(src0, 0) * n_threads
+        }
+        _ => 0,
+    }
+}
+
+/// Convolutions keep their operands as F16 or F32, after the kernel's type.
+fn conv_element_size(kernel: Option<&ggml_tensor>) -> usize {
+    match kernel.map(|t| t.type_) {
+        Some(ggml_internal::ggml_type_GGML_TYPE_F16) => F16_SIZE,
+        _ => F32_SIZE,
+    }
+}
+
+fn up(n: i64, m: i64) -> i64 {
+    (n + m - 1) / m * m
+}
+
+fn up32(n: usize) -> usize {
+    up(n as i64, 32) as usize
+}